@@ -1,11 +1,28 @@
-use std::io::{Read, Write};
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use clap::Subcommand;
+use clap::{Args, Subcommand};
 use owo_colors::OwoColorize;
-use remove_dir_all::remove_dir_contents;
+use remove_dir_all::remove_dir_all;
+use semver::{Version, VersionReq};
+use serde::Serialize;
 use walkdir::WalkDir;
 
-use crate::data::{config::Config, package::PackageConfig};
+use crate::{
+    data::{
+        config::Config,
+        dependency::{
+            dir_size, ArtifactChecksums, CacheMetadata, Dependency, IncludeRewrite,
+            SharedDependency,
+        },
+        package::{PackageConfig, SharedPackageConfig},
+        qpackages,
+    },
+    utils::{git, hash, lock, sync},
+};
 
 #[derive(clap::Args, Debug, Clone)]
 
@@ -20,26 +37,89 @@ pub enum CacheOperation {
     /// Clear the cache
     Clear,
     /// Lists versions for each cached package
-    List,
+    List(ListArgs),
     /// Shows you the current cache path
     Path,
-    /// Fixes some dependencies that use technically wrong include paths
-    LegacyFix,
+    /// Applies the include rewrite rules of the global config to every cached package again, undoing rules that no longer apply
+    Rewrite,
+    /// Removes packages that were not used recently, or that don't fit in the max cache size
+    Gc(GcArgs),
+    /// Checks cached packages for broken or half finished downloads
+    Verify(VerifyArgs),
+    /// Checks cached packages and downloads the broken parts again
+    Repair(VerifyArgs),
+    /// Installs the current package into the cache, so other local packages can restore it without publishing
+    Add(AddArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct AddArgs {
+    /// The built .so or .a, defaults to build/<so name>
+    #[clap(long)]
+    pub so: Option<PathBuf>,
+    /// The built debug .so or .a, defaults to build/debug/<so name>
+    #[clap(long)]
+    pub debug_so: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct VerifyArgs {
+    /// Only check this package id
+    pub package: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ListArgs {
+    /// Only list versions of this package id
+    pub package: Option<String>,
+    /// Also show sizes, sources and when versions were downloaded and used
+    #[clap(short, long)]
+    pub verbose: bool,
+    /// Print everything as json instead
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GcArgs {
+    /// Remove package versions that were not restored in this many days
+    #[clap(long)]
+    pub days: Option<u64>,
+    /// Size in megabytes to shrink the cache to, defaults to the configured max cache size
+    #[clap(long)]
+    pub max_size: Option<u64>,
+    /// Only print what would be removed
+    #[clap(long)]
+    pub dry_run: bool,
 }
 
 pub fn execute_cache_operation(operation: Cache) {
     match operation.op {
         CacheOperation::Clear => clear(),
-        CacheOperation::List => list(),
+        CacheOperation::List(l) => list(l),
         CacheOperation::Path => path(),
-        CacheOperation::LegacyFix => legacy_fix(),
+        CacheOperation::Rewrite => rewrite(),
+        CacheOperation::Gc(g) => gc(g),
+        CacheOperation::Verify(v) => verify(v, false),
+        CacheOperation::Repair(v) => verify(v, true),
+        CacheOperation::Add(a) => add(a),
     }
 }
 
 fn clear() {
     let config = Config::read_combine();
     let path = config.cache.unwrap();
-    remove_dir_contents(path).expect("Failed to remove cached folders");
+    for entry in std::fs::read_dir(&path).into_iter().flatten().flatten() {
+        // other processes could be holding or waiting on a lock, removing it would let a third one in
+        if entry.file_name() == lock::LOCKS_DIR {
+            continue;
+        }
+        if entry.path().is_dir() {
+            remove_dir_all(entry.path()).expect("Failed to remove cached folders");
+        } else {
+            std::fs::remove_file(entry.path()).expect("Failed to remove cached file");
+        }
+    }
 }
 
 fn path() {
@@ -50,12 +130,36 @@ fn path() {
     );
 }
 
-fn list() {
+fn list(args: ListArgs) {
     let config = Config::read_combine();
     let path = config.cache.unwrap();
 
-    for dir in WalkDir::new(&path).max_depth(2).min_depth(1) {
+    if args.json {
+        list_json(&path, args.package.as_deref());
+        return;
+    } else if args.verbose {
+        list_verbose(&path, args.package.as_deref());
+        return;
+    }
+
+    for dir in WalkDir::new(&path)
+        .max_depth(2)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|dir| {
+            dir.depth() != 1
+                || !is_hidden(dir)
+                    && args
+                        .package
+                        .as_ref()
+                        .map_or(true, |id| dir.file_name().to_string_lossy() == *id)
+        })
+    {
         let unwrapped = dir.unwrap();
+        if !unwrapped.file_type().is_dir() {
+            continue;
+        }
+
         if unwrapped.depth() == 1 {
             println!(
                 "package {}:",
@@ -70,70 +174,783 @@ fn list() {
     }
 }
 
-fn legacy_fix() {
-    for entry in WalkDir::new(Config::read_combine().cache.unwrap())
-        .min_depth(2)
-        .max_depth(2)
+fn list_verbose(cache_path: &Path, package: Option<&str>) {
+    let mut current_id = String::new();
+    for entry in cache_entries(cache_path)
+        .into_iter()
+        .filter(|entry| package.map_or(true, |id| entry.id == id))
     {
-        let path = entry.unwrap().into_path().join("src");
-        println!("{}", path.display());
-        let qpm_path = path.join("qpm.json");
-        if !qpm_path.exists() {
-            continue;
+        if entry.id != current_id {
+            println!("package {}:", entry.id.bright_red());
+            current_id = entry.id.clone();
         }
-        let shared_path = path.join(PackageConfig::read_path(qpm_path).shared_dir);
-
-        for entry in WalkDir::new(shared_path) {
-            let entry_path = entry.unwrap().into_path();
-            if entry_path.is_file() {
-                let mut file = match std::fs::File::open(&entry_path) {
-                    Ok(o) => o,
-                    Err(e) => panic!(
-                        "Opening file {} to read failed: {}",
-                        entry_path.display().bright_yellow(),
-                        e
-                    ),
-                };
-
-                let mut buf: String = "".to_string();
-                match file.read_to_string(&mut buf) {
-                    Ok(_) => {}
-                    Err(_e) => {
-                        #[cfg(debug_assertions)]
-                        println!(
-                            "reading file {} to string failed: {}",
-                            entry_path.display().bright_yellow(),
-                            _e
-                        );
-                        continue;
+
+        println!(
+            " - {} {}, last used {}",
+            entry.version.bright_green(),
+            format_size(entry.size).bright_yellow(),
+            format_age(entry.last_used)
+        );
+
+        match &entry.metadata {
+            Some(metadata) => {
+                if let Some(source_url) = &metadata.source_url {
+                    print!("     source: {}", source_url.bright_yellow());
+                    if let Some(branch) = &metadata.branch {
+                        print!(" ({})", branch);
+                    }
+                    if let Some(commit) = &metadata.commit {
+                        print!(" at {}", commit);
+                    }
+                    if let Some(downloaded) = metadata.source_downloaded {
+                        print!(", downloaded {}", format_age(timestamp(downloaded)));
+                    }
+                    println!();
+                }
+
+                if let Some(so_link) = &metadata.so_link {
+                    print!("     so: {}", so_link.bright_yellow());
+                    if let Some(downloaded) = metadata.so_downloaded {
+                        print!(", downloaded {}", format_age(timestamp(downloaded)));
+                    }
+                    println!();
+                }
+
+                if let Some(debug_so_link) = &metadata.debug_so_link {
+                    print!("     debug so: {}", debug_so_link.bright_yellow());
+                    if let Some(downloaded) = metadata.debug_so_downloaded {
+                        print!(", downloaded {}", format_age(timestamp(downloaded)));
                     }
-                };
-                fs_extra::file::remove(&entry_path).unwrap_or_else(|_| {
-                    panic!(
-                        "removing file {} failed",
-                        entry_path.display().bright_yellow()
-                    )
-                });
-                let mut file = std::fs::File::create(&entry_path).unwrap_or_else(|_| {
-                    panic!(
-                        "opening file {} to write failed",
-                        entry_path.display().bright_yellow()
-                    )
-                });
-                file.write_all(
-                    buf.replace(
-                        "#include \"extern/beatsaber-hook/",
-                        "#include \"beatsaber-hook/",
-                    )
-                    .as_bytes(),
-                )
-                .unwrap_or_else(|_| {
-                    panic!(
-                        "writing file {} failed",
-                        entry_path.display().bright_yellow()
-                    )
-                });
+                    println!();
+                }
+            }
+            None => println!("     cached before qpm recorded where packages came from"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListedEntry<'a> {
+    id: &'a str,
+    version: &'a str,
+    path: &'a Path,
+    size: u64,
+    last_used: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a CacheMetadata>,
+}
+
+fn list_json(cache_path: &Path, package: Option<&str>) {
+    let entries = cache_entries(cache_path);
+    let listed: Vec<ListedEntry> = entries
+        .iter()
+        .filter(|entry| package.map_or(true, |id| entry.id == id))
+        .map(|entry| ListedEntry {
+            id: &entry.id,
+            version: &entry.version,
+            path: &entry.path,
+            size: entry.size,
+            last_used: entry
+                .last_used
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            metadata: entry.metadata.as_ref(),
+        })
+        .collect();
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&listed).expect("Serialization failed")
+    );
+}
+
+fn timestamp(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+fn format_age(time: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(time)
+        .map_or(0, |d| d.as_secs());
+
+    if secs < 60 * 60 {
+        format!("{} minutes ago", secs / 60)
+    } else if secs < 24 * 60 * 60 {
+        format!("{} hours ago", secs / (60 * 60))
+    } else {
+        format!("{} days ago", secs / (24 * 60 * 60))
+    }
+}
+
+/// A single cached package version
+struct CacheEntry {
+    id: String,
+    version: String,
+    path: PathBuf,
+    last_used: SystemTime,
+    size: u64,
+    metadata: Option<CacheMetadata>,
+}
+
+impl CacheEntry {
+    fn is_mirror(&self) -> bool {
+        self.id == git::MIRRORS_DIR
+    }
+}
+
+fn cache_entries(cache_path: &Path) -> Vec<CacheEntry> {
+    // walkdir never asks filter_entry about entries above min_depth, so the ids are walked too
+    WalkDir::new(cache_path)
+        .min_depth(1)
+        .max_depth(2)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !is_hidden(entry))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.depth() == 2 && entry.file_type().is_dir())
+        .map(|entry| {
+            let path = entry.into_path();
+            CacheEntry {
+                id: path
+                    .parent()
+                    .unwrap()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                version: path.file_name().unwrap().to_string_lossy().to_string(),
+                last_used: last_used(&path),
+                size: dir_size(&path),
+                metadata: CacheMetadata::read_path(&path),
+                path,
+            }
+        })
+        .collect()
+}
+
+/// The repo mirrors packages were checked out from, as entries with the mirrors folder as id and the mirror as version
+fn mirror_entries(cache_path: &Path) -> Vec<CacheEntry> {
+    std::fs::read_dir(cache_path.join(git::MIRRORS_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .map(|path| CacheEntry {
+            id: git::MIRRORS_DIR.to_string(),
+            version: path.file_name().unwrap().to_string_lossy().to_string(),
+            last_used: last_used(&path),
+            size: dir_size(&path),
+            metadata: None,
+            path,
+        })
+        .collect()
+}
+
+/// Folders like the repo mirrors are kept next to packages, but are not packages themselves
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    entry.depth() == 1 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// When the entry was last restored, entries that were never restored count from when they were cached
+fn last_used(entry_path: &Path) -> SystemTime {
+    std::fs::read_to_string(entry_path.join("last_used"))
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .or_else(|| {
+            std::fs::metadata(entry_path)
+                .and_then(|m| m.modified())
+                .ok()
+        })
+        .unwrap_or(UNIX_EPOCH)
+}
+
+fn gc(args: GcArgs) {
+    let config = Config::read_combine();
+    let cache_path = config.cache.unwrap();
+    let max_size = args.max_size.or(config.max_cache_size);
+
+    if args.days.is_none() && max_size.is_none() {
+        println!(
+            "Nothing to do, pass {} or {}, or configure a max size with {}",
+            "--days".bright_yellow(),
+            "--max-size".bright_yellow(),
+            "qpm config cache max-size".bright_yellow()
+        );
+        return;
+    }
+
+    let mut entries = cache_entries(&cache_path);
+    // mirrors take up space too, and are removed like packages once nothing was checked out from them in a while
+    entries.extend(mirror_entries(&cache_path));
+    // least recently used first
+    entries.sort_by_key(|entry| entry.last_used);
+
+    let mut to_remove: Vec<CacheEntry> = Vec::new();
+    if let Some(days) = args.days {
+        let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+        let (old, recent): (Vec<CacheEntry>, Vec<CacheEntry>) = entries
+            .into_iter()
+            .partition(|entry| entry.last_used < cutoff);
+        to_remove.extend(old);
+        entries = recent;
+    }
+
+    if let Some(max_size) = max_size {
+        let budget = max_size * 1024 * 1024;
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut remaining = entries.into_iter();
+        while total > budget {
+            match remaining.next() {
+                Some(entry) => {
+                    total -= entry.size;
+                    to_remove.push(entry);
+                }
+                None => break,
+            }
+        }
+    }
+
+    let mut freed: u64 = 0;
+    for entry in to_remove.iter() {
+        let _entry_lock = match lock::try_lock_exclusive(&lock::entry_lock_path(&entry.path)) {
+            Some(entry_lock) => entry_lock,
+            None => {
+                println!(
+                    "Skipping {} {}, another qpm process is using it",
+                    entry.id.bright_red(),
+                    entry.version.bright_green()
+                );
+                continue;
+            }
+        };
+
+        if entry.is_mirror() {
+            println!(
+                "Removing mirror {} ({} MB)",
+                entry.version.bright_yellow(),
+                (entry.size / (1024 * 1024)).bright_yellow()
+            );
+        } else {
+            println!(
+                "Removing {} {} ({} MB)",
+                entry.id.bright_red(),
+                entry.version.bright_green(),
+                (entry.size / (1024 * 1024)).bright_yellow()
+            );
+        }
+        freed += entry.size;
+
+        if args.dry_run {
+            continue;
+        }
+
+        remove_dir_all(&entry.path).expect("Failed to remove cached folder");
+        let id_path = entry.path.parent().unwrap();
+        if std::fs::read_dir(id_path).map_or(false, |mut dir| dir.next().is_none()) {
+            std::fs::remove_dir(id_path).expect("Failed to remove cached folder");
+        }
+    }
+
+    println!(
+        "{} {} package versions and mirrors, freeing {} MB",
+        if args.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        },
+        to_remove.len().bright_yellow(),
+        (freed / (1024 * 1024)).bright_yellow()
+    );
+}
+
+/// Something that is wrong with a cache entry
+enum Problem {
+    /// a download or clone was interrupted before it was moved into src
+    LeftoverTmp,
+    /// libs were downloaded, but the sources are missing
+    MissingSrc,
+    /// src exists, but the package config that every package has is not in it
+    MissingPackageConfig,
+    /// the cached qpm.json is for another version
+    VersionMismatch(Version),
+    /// a lib that the package links is not in the cache
+    MissingLib(PathBuf),
+    /// a lib that is empty, cut off, not a library at all or not the one its checksum says
+    BrokenLib(PathBuf, &'static str),
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::LeftoverTmp => write!(f, "leftover tmp folder from an interrupted download"),
+            Problem::MissingSrc => write!(f, "src folder is missing"),
+            Problem::MissingPackageConfig => write!(f, "src folder has no qpm.json"),
+            Problem::VersionMismatch(version) => {
+                write!(f, "cached qpm.json is for version {}", version)
             }
+            Problem::MissingLib(path) => write!(f, "{} is missing", path.display()),
+            Problem::BrokenLib(path, reason) => write!(f, "{} is {}", path.display(), reason),
         }
     }
 }
+
+fn find_problems(
+    entry: &CacheEntry,
+    version: &Version,
+    recorded: Option<&ArtifactChecksums>,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let src_path = entry.path.join("src");
+    let lib_path = entry.path.join("lib");
+
+    if tmp_folders(&entry.path).next().is_some() {
+        problems.push(Problem::LeftoverTmp);
+    }
+
+    if !src_path.exists() {
+        problems.push(Problem::MissingSrc);
+        return problems;
+    }
+
+    let package_path = src_path.join("qpm.json");
+    if !package_path.exists() {
+        problems.push(Problem::MissingPackageConfig);
+        return problems;
+    }
+
+    let package = PackageConfig::read_path(package_path);
+    if package.info.version != *version {
+        problems.push(Problem::VersionMismatch(package.info.version.clone()));
+    }
+
+    if package.info.additional_data.headers_only.unwrap_or(false) {
+        return problems;
+    }
+
+    let so_name = package.get_so_name();
+    let published = ArtifactChecksums::published(&package.info.additional_data);
+    let libs = [
+        (
+            package.info.additional_data.so_link.is_some(),
+            so_name.clone(),
+            recorded.and_then(|checksums| checksums.so.as_ref()),
+            published.so.as_ref(),
+        ),
+        (
+            package.info.additional_data.debug_so_link.is_some(),
+            format!("debug_{}", so_name),
+            recorded.and_then(|checksums| checksums.debug_so.as_ref()),
+            published.debug_so.as_ref(),
+        ),
+    ];
+
+    for (expected, name, recorded, published) in libs {
+        let path = lib_path.join(name);
+        if !path.exists() {
+            if expected {
+                problems.push(Problem::MissingLib(path));
+            }
+        } else if let Some(reason) =
+            check_lib(&path).or_else(|| check_lib_checksum(&path, recorded, published))
+        {
+            problems.push(Problem::BrokenLib(path, reason));
+        }
+    }
+
+    problems
+}
+
+/// Staging folders that were left behind, only call this while holding the entry lock
+fn tmp_folders(entry_path: &Path) -> impl Iterator<Item = PathBuf> {
+    std::fs::read_dir(entry_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("tmp"))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+}
+
+/// Checks the lib is not empty, starts like a shared or static library should and is as long as its headers say
+fn check_lib(path: &Path) -> Option<&'static str> {
+    let mut file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Some("unreadable"),
+    };
+    let len = file.metadata().map_or(0, |metadata| metadata.len());
+
+    let mut header = Vec::with_capacity(64);
+    (&mut file).take(64).read_to_end(&mut header).ok();
+
+    if header.is_empty() {
+        Some("empty")
+    } else if header.starts_with(b"\x7fELF") {
+        if elf_complete(&header, len) {
+            None
+        } else {
+            Some("cut off, its headers point past the end of the file")
+        }
+    } else if header.starts_with(b"!<arch>\n") {
+        if archive_complete(&mut file, len) {
+            None
+        } else {
+            Some("cut off, its last member is shorter than its header says")
+        }
+    } else {
+        Some("not a shared or static library, the download was probably cut off or an error page")
+    }
+}
+
+/// Whether the program and section header tables the ELF header points at are inside the file
+fn elf_complete(header: &[u8], len: u64) -> bool {
+    let is_64 = match header.get(4) {
+        Some(1) => false,
+        Some(2) => true,
+        _ => return false,
+    };
+    let little_endian = match header.get(5) {
+        Some(1) => true,
+        Some(2) => false,
+        _ => return false,
+    };
+    if header.len() < if is_64 { 64 } else { 52 } {
+        return false;
+    }
+
+    let field = |offset: usize, size: usize| -> u64 {
+        let bytes = &header[offset..offset + size];
+        let push = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+        if little_endian {
+            bytes.iter().rev().fold(0, push)
+        } else {
+            bytes.iter().fold(0, push)
+        }
+    };
+
+    // (offset, entry size, entry count) of the program and section header tables
+    let tables = if is_64 {
+        [
+            (field(0x20, 8), field(0x36, 2), field(0x38, 2)),
+            (field(0x28, 8), field(0x3A, 2), field(0x3C, 2)),
+        ]
+    } else {
+        [
+            (field(0x1C, 4), field(0x2A, 2), field(0x2C, 2)),
+            (field(0x20, 4), field(0x2E, 2), field(0x30, 2)),
+        ]
+    };
+
+    tables.iter().all(|(offset, entry_size, count)| {
+        offset
+            .checked_add(entry_size * count)
+            .map_or(false, |end| end <= len)
+    })
+}
+
+/// Whether every member of a static library is as long as its header says
+fn archive_complete(file: &mut std::fs::File, len: u64) -> bool {
+    let mut offset = 8;
+    while offset < len {
+        let mut header = [0u8; 60];
+        if file.seek(SeekFrom::Start(offset)).is_err() || file.read_exact(&mut header).is_err() {
+            return false;
+        }
+        if &header[58..] != b"`\n" {
+            return false;
+        }
+
+        let size = match std::str::from_utf8(&header[48..58])
+            .ok()
+            .and_then(|size| size.trim().parse::<u64>().ok())
+        {
+            Some(size) => size,
+            None => return false,
+        };
+
+        let end = offset + 60 + size;
+        if end > len {
+            return false;
+        }
+        // members start on even offsets
+        offset = end + end % 2;
+    }
+    true
+}
+
+/// Compares the lib against the checksum recorded in qpm.shared.json, or else the one its publisher declared
+fn check_lib_checksum(
+    path: &Path,
+    recorded: Option<&String>,
+    published: Option<&String>,
+) -> Option<&'static str> {
+    let (expected, reason) = match (recorded, published) {
+        (Some(recorded), _) => (
+            recorded,
+            "not the lib that was recorded in qpm.shared.json, its checksum differs",
+        ),
+        (None, Some(published)) => (
+            published,
+            "not the lib that was published, its checksum differs",
+        ),
+        (None, None) => return None,
+    };
+
+    if hash::sha256_file(path) == *expected {
+        None
+    } else {
+        Some(reason)
+    }
+}
+
+fn verify(args: VerifyArgs, repair: bool) {
+    let cache_path = Config::read_combine().cache.unwrap();
+    // repairing always works on the cache, never on a vendored copy
+    qpackages::set_vendor_dir(None);
+
+    // checksums of the libs restored into this project, if verify runs in one
+    let restored = if Path::new("qpm.shared.json").exists() {
+        SharedPackageConfig::read().restored_dependencies
+    } else {
+        Vec::new()
+    };
+
+    let mut broken = 0;
+    for entry in cache_entries(&cache_path) {
+        if args.package.as_ref().map_or(false, |id| *id != entry.id) {
+            continue;
+        }
+
+        let version = match entry.version.parse::<Version>() {
+            Ok(version) => version,
+            Err(_) => continue,
+        };
+
+        let entry_lock = match lock::try_lock_exclusive(&lock::entry_lock_path(&entry.path)) {
+            Some(entry_lock) => entry_lock,
+            None => {
+                println!(
+                    "Skipping {} {}, another qpm process is using it",
+                    entry.id.bright_red(),
+                    entry.version.bright_green()
+                );
+                continue;
+            }
+        };
+
+        let recorded = restored
+            .iter()
+            .find(|dep| dep.dependency.id == entry.id && dep.version == version)
+            .and_then(|dep| dep.checksums.as_ref());
+        let problems = find_problems(&entry, &version, recorded);
+        if problems.is_empty() {
+            continue;
+        }
+
+        broken += 1;
+        println!(
+            "package {} {}:",
+            entry.id.bright_red(),
+            entry.version.bright_green()
+        );
+        for problem in problems.iter() {
+            println!(" - {}", problem.to_string().bright_yellow());
+        }
+
+        if repair {
+            repair_entry(&entry, &version, &problems, recorded, entry_lock);
+        }
+    }
+
+    if broken == 0 {
+        println!("No problems found in the cache");
+    } else if repair {
+        println!("Repaired {} package versions", broken.bright_yellow());
+    } else {
+        println!(
+            "Found {} broken package versions, run {} to fix them",
+            broken.bright_yellow(),
+            "qpm cache repair".bright_yellow()
+        );
+    }
+}
+
+/// Removes only the broken parts of the entry, then lets caching download them again
+///
+/// The downloads are checked against the checksums recorded in the project, if there are any
+fn repair_entry(
+    entry: &CacheEntry,
+    version: &Version,
+    problems: &[Problem],
+    recorded: Option<&ArtifactChecksums>,
+    entry_lock: std::fs::File,
+) {
+    for problem in problems.iter() {
+        match problem {
+            Problem::LeftoverTmp => {
+                for tmp_path in tmp_folders(&entry.path) {
+                    remove_dir_all(tmp_path).expect("Failed to remove tmp folder");
+                }
+            }
+            Problem::MissingPackageConfig | Problem::VersionMismatch(_) => {
+                remove_dir_all(entry.path.join("src")).expect("Failed to remove src folder");
+                std::fs::remove_file(entry.path.join("checksums.json")).ok();
+            }
+            Problem::BrokenLib(path, _) => {
+                std::fs::remove_file(path).expect("Failed to remove broken lib")
+            }
+            Problem::MissingSrc | Problem::MissingLib(_) => {}
+        }
+    }
+
+    // caching takes the lock itself
+    drop(entry_lock);
+
+    let resolved = qpackages::get_resolved_package(None, &entry.id, version);
+    let mut shared_dep = SharedDependency {
+        registry: resolved.registry,
+        dependency: Dependency {
+            id: entry.id.clone(),
+            version_range: VersionReq::parse(&format!("={}", version)).unwrap(),
+            additional_data: resolved.package.config.info.additional_data,
+        },
+        version: version.clone(),
+        checksums: recorded.cloned(),
+        commit: None,
+    };
+    shared_dep.cache();
+}
+
+fn add(args: AddArgs) {
+    let shared_package = SharedPackageConfig::read();
+    let package = &shared_package.config;
+    let so_name = package.get_so_name();
+
+    let so_path = args.so.unwrap_or_else(|| Path::new("build").join(&so_name));
+    let debug_so_path = args
+        .debug_so
+        .unwrap_or_else(|| Path::new("build").join("debug").join(&so_name));
+
+    let headers_only = package.info.additional_data.headers_only.unwrap_or(false);
+    if !headers_only && !so_path.exists() {
+        panic!(
+            "Built lib {} does not exist, build the package first or pass {}",
+            so_path.display().bright_yellow(),
+            "--so".bright_yellow()
+        );
+    }
+
+    let entry_path = Config::read_combine()
+        .cache
+        .unwrap()
+        .join(&package.info.id)
+        .join(package.info.version.to_string());
+    std::fs::create_dir_all(&entry_path).expect("Failed to create cache folder");
+    let _entry_lock = lock::lock_exclusive(&lock::entry_lock_path(&entry_path));
+
+    // replace whatever was cached for this version before
+    for entry in std::fs::read_dir(&entry_path).unwrap().flatten() {
+        if entry.path().is_dir() {
+            remove_dir_all(entry.path()).expect("Failed to remove cached folder");
+        } else {
+            std::fs::remove_file(entry.path()).expect("Failed to remove cached file");
+        }
+    }
+
+    // staged first, so src only appears once it is complete
+    let tmp_path = entry_path.join(format!("tmp-{}", std::process::id()));
+    let mut to_copy = vec![PathBuf::from("qpm.json"), package.shared_dir.clone()];
+    if let Some(extra_files) = &package.info.additional_data.extra_files {
+        to_copy.extend(extra_files.iter().map(PathBuf::from));
+    }
+
+    for from in to_copy.iter() {
+        // qpm.json could point anywhere, only files in the package belong in the cache
+        if !sync::is_contained(from) {
+            println!(
+                "{} is not inside the package, not adding it",
+                from.display().bright_yellow()
+            );
+            continue;
+        }
+
+        let to = tmp_path.join(from);
+        std::fs::create_dir_all(to.parent().unwrap()).expect("Failed to create parent folder");
+        if from.is_dir() {
+            let mut options = fs_extra::dir::CopyOptions::new();
+            options.copy_inside = true;
+            options.content_only = true;
+            std::fs::create_dir_all(&to).expect("Failed to create destination folder");
+            fs_extra::dir::copy(from, &to, &options).expect("Failed to copy directory!");
+        } else if from.is_file() {
+            std::fs::copy(from, &to).expect("Failed to copy file!");
+        } else {
+            println!(
+                "{} does not exist, not adding it",
+                from.display().bright_yellow()
+            );
+        }
+    }
+
+    // without a debug lib in the cache, restoring would try to download the published one instead
+    let mut seeded_package = shared_package.clone();
+    if !debug_so_path.exists() {
+        seeded_package.config.info.additional_data.debug_so_link = None;
+        let file = std::fs::File::create(tmp_path.join("qpm.json")).expect("create failed");
+        serde_json::to_writer_pretty(file, &seeded_package.config).expect("Serialization failed");
+    }
+    std::fs::rename(&tmp_path, entry_path.join("src")).expect("Failed to move folder");
+
+    let lib_path = entry_path.join("lib");
+    std::fs::create_dir_all(&lib_path).expect("Failed to create lib path");
+    if so_path.exists() {
+        std::fs::copy(&so_path, lib_path.join(&so_name)).expect("Failed to copy lib");
+    }
+    if debug_so_path.exists() {
+        std::fs::copy(&debug_so_path, lib_path.join(format!("debug_{}", so_name)))
+            .expect("Failed to copy lib");
+    }
+
+    // lets restore resolve this version without any registry knowing about it
+    let file = std::fs::File::create(entry_path.join("qpm.shared.json")).expect("create failed");
+    serde_json::to_writer_pretty(file, &seeded_package).expect("Serialization failed");
+
+    let mut metadata = CacheMetadata::new(&package.info.id, &package.info.version);
+    metadata.source_url = Some(std::env::current_dir().unwrap().display().to_string());
+    metadata.source_downloaded = Some(CacheMetadata::now());
+    metadata.size = Some(dir_size(&entry_path));
+    metadata.write_path(&entry_path);
+
+    println!(
+        "Added {} {} to the cache",
+        package.info.id.bright_red(),
+        package.info.version.bright_green()
+    );
+}
+
+fn rewrite() {
+    // rules of the current project are only applied when it restores, the cache is shared
+    let rules = IncludeRewrite::for_cache();
+    for entry in cache_entries(&Config::read_combine().cache.unwrap()) {
+        if !entry.path.join("src").join("qpm.json").exists() {
+            continue;
+        }
+        let version = match Version::parse(&entry.version) {
+            Ok(version) => version,
+            Err(_) => continue,
+        };
+
+        let _entry_lock = lock::lock_exclusive(&lock::entry_lock_path(&entry.path));
+        IncludeRewrite::sync_entry(&entry.path, &entry.id, &version, &rules);
+    }
+}
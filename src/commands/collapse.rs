@@ -5,7 +5,7 @@ use crate::data::package;
 pub fn execute_collapse_operation() {
     let package = package::PackageConfig::read();
     let resolved = package.resolve();
-    for shared_package in resolved {
+    for shared_package in resolved.map(|resolved| resolved.package) {
         println!(
             "{}: ({}) --> {} ({} restored dependencies)",
             &shared_package.config.info.id.bright_red(),
@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+
+use crate::{data::config::Config as AppConfig, utils::network};
+
+#[derive(Args, Debug, Clone)]
+pub struct CaCertificates {
+    #[clap(subcommand)]
+    pub op: Option<CaCertificatesOperation>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CaCertificatesOperation {
+    /// Trust the root certificate in a pem or der file
+    Add(CaCertificateArgs),
+    /// Stop trusting a root certificate that was added
+    Remove(CaCertificateArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CaCertificateArgs {
+    /// Path to the certificate, files ending in .der are read as der and anything else as pem
+    pub path: PathBuf,
+}
+
+pub fn execute_ca_certificates_config_operation(
+    config: &mut AppConfig,
+    operation: CaCertificates,
+) -> bool {
+    match operation.op {
+        Some(CaCertificatesOperation::Add(a)) => {
+            // the config is used from any folder, so a relative path would stop working
+            let path = a.path.canonicalize().unwrap_or_else(|e| {
+                panic!(
+                    "Certificate {} could not be found: {}",
+                    a.path.display().bright_yellow(),
+                    e
+                )
+            });
+            // a certificate that doesn't parse would break every web request later
+            network::read_certificate(&path);
+
+            let mut certificates = config.ca_certificates.take().unwrap_or_default();
+            if certificates.contains(&path) {
+                println!(
+                    "Certificate {} was already added!",
+                    path.display().bright_yellow()
+                );
+                config.ca_certificates = Some(certificates);
+                return false;
+            }
+
+            println!("Added certificate {}", path.display().bright_yellow());
+            certificates.push(path);
+            config.ca_certificates = Some(certificates);
+            true
+        }
+        Some(CaCertificatesOperation::Remove(r)) => {
+            // removed certificates may be gone already, so both the given and the full path are matched
+            let full_path = r.path.canonicalize().unwrap_or_else(|_| r.path.clone());
+            let mut certificates = config.ca_certificates.take().unwrap_or_default();
+            let len = certificates.len();
+            certificates.retain(|path| path != &r.path && path != &full_path);
+            let removed = certificates.len() != len;
+            config.ca_certificates = if certificates.is_empty() {
+                None
+            } else {
+                Some(certificates)
+            };
+
+            if !removed {
+                println!(
+                    "Certificate {} was not added!",
+                    r.path.display().bright_yellow()
+                );
+                return false;
+            }
+            println!("Removed certificate {}", r.path.display().bright_yellow());
+            true
+        }
+        None => {
+            match &config.ca_certificates {
+                Some(certificates) if !certificates.is_empty() => {
+                    println!("Extra trusted root certificates:");
+                    for path in certificates.iter() {
+                        println!(" - {}", path.display().bright_yellow());
+                    }
+                }
+                _ => println!("No extra root certificates are trusted!"),
+            }
+            false
+        }
+    }
+}
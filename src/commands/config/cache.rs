@@ -15,6 +15,8 @@ pub struct Cache {
 pub enum CacheOperation {
     /// Gets or sets the path to place the QPM Cache
     Path(CacheSetPathOperation),
+    /// Gets or sets the size in megabytes that qpm cache gc shrinks the cache to
+    MaxSize(CacheMaxSizeOperation),
 }
 
 #[derive(Args, Debug, Clone)]
@@ -22,6 +24,15 @@ pub struct CacheSetPathOperation {
     pub path: Option<PathBuf>,
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct CacheMaxSizeOperation {
+    /// The size in megabytes
+    pub size: Option<u64>,
+    /// Remove the size limit
+    #[clap(long, conflicts_with = "size")]
+    pub clear: bool,
+}
+
 pub fn execute_cache_config_operation(config: &mut AppConfig, operation: Cache) -> bool {
     match operation.op {
         CacheOperation::Path(p) => {
@@ -73,6 +84,24 @@ pub fn execute_cache_config_operation(config: &mut AppConfig, operation: Cache)
                 println!("Cache path is not configured!");
             }
         }
+        CacheOperation::MaxSize(m) => {
+            if m.clear {
+                println!("Removed the max cache size!");
+                config.max_cache_size = None;
+                return true;
+            } else if let Some(size) = m.size {
+                println!("Set max cache size to {} MB!", size.bright_yellow());
+                config.max_cache_size = Some(size);
+                return true;
+            } else if let Some(size) = config.max_cache_size {
+                println!(
+                    "Current configured max cache size is {} MB",
+                    size.bright_yellow()
+                );
+            } else {
+                println!("Max cache size is not configured!");
+            }
+        }
     }
 
     false
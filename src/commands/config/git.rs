@@ -0,0 +1,42 @@
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+
+use crate::data::config::Config as AppConfig;
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum GitOperation {
+    /// Clone sources with git
+    Enable,
+    /// Download the archive of a repo instead of cloning it. Patches for dependencies in qpm.json are applied with git, so they still need it installed
+    Disable,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct Git {
+    #[clap(subcommand)]
+    pub op: Option<GitOperation>,
+}
+
+pub fn execute_git_config_operation(config: &mut AppConfig, operation: Git) -> bool {
+    if let Some(git) = operation.op {
+        let value = matches!(git, GitOperation::Enable);
+        println!("Set git usage to {}", value.bright_yellow());
+        if !value {
+            println!(
+                "Patches for dependencies are still applied with git, packages with {} in their qpm.json need it installed",
+                "patches".bright_yellow()
+            );
+        }
+        config.use_git = Some(value);
+        return true;
+    } else if let Some(git) = config.use_git.as_ref() {
+        println!(
+            "Current configured git usage is set to: {}",
+            git.bright_yellow()
+        );
+    } else {
+        println!("Git usage is not configured, git is used when it is installed!");
+    }
+
+    false
+}
@@ -0,0 +1,81 @@
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+
+use crate::{
+    data::config::{normalize_host, Config as AppConfig, GitHost as AppGitHost},
+    utils::git_host::HostKind,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct GitHost {
+    #[clap(subcommand)]
+    pub op: Option<GitHostOperation>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum GitHostOperation {
+    /// Set what kind of server a host is
+    Set(GitHostSetArgs),
+    /// Remove a host, its kind is guessed from its name again
+    Remove(GitHostRemoveArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GitHostSetArgs {
+    /// The host, like git.company.com
+    pub host: String,
+    /// github, gitlab, gitea (also for forgejo) or generic
+    pub kind: HostKind,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GitHostRemoveArgs {
+    /// The host, like git.company.com
+    pub host: String,
+}
+
+pub fn execute_git_host_config_operation(config: &mut AppConfig, operation: GitHost) -> bool {
+    let mut git_hosts = config.git_hosts.clone().unwrap_or_default();
+    match operation.op {
+        Some(GitHostOperation::Set(s)) => {
+            let host = normalize_host(&s.host);
+            git_hosts.retain(|git_host| git_host.host != host);
+            println!(
+                "Set the kind of {} to {}",
+                host.bright_yellow(),
+                s.kind.bright_green()
+            );
+            git_hosts.push(AppGitHost { host, kind: s.kind });
+        }
+        Some(GitHostOperation::Remove(r)) => {
+            let host = normalize_host(&r.host);
+            let len = git_hosts.len();
+            git_hosts.retain(|git_host| git_host.host != host);
+            if git_hosts.len() == len {
+                println!("The kind of {} was not configured!", host.bright_yellow());
+                return false;
+            }
+            println!("Removed the kind of {}", host.bright_yellow());
+        }
+        None => {
+            if git_hosts.is_empty() {
+                println!("No git host kinds are configured, github.com, gitlab and codeberg.org are known by name, other hosts are generic unless they answer the gitea api when an archive is needed!");
+            }
+            for git_host in git_hosts.iter() {
+                println!(
+                    " - {}: {}",
+                    git_host.host.bright_yellow(),
+                    git_host.kind.bright_green()
+                );
+            }
+            return false;
+        }
+    }
+
+    config.git_hosts = if git_hosts.is_empty() {
+        None
+    } else {
+        Some(git_hosts)
+    };
+    true
+}
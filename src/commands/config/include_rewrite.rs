@@ -0,0 +1,117 @@
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+use semver::VersionReq;
+
+use crate::data::{config::Config as AppConfig, dependency::IncludeRewrite as AppIncludeRewrite};
+
+#[derive(Args, Debug, Clone)]
+pub struct IncludeRewrite {
+    #[clap(subcommand)]
+    pub op: Option<IncludeRewriteOperation>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum IncludeRewriteOperation {
+    /// Add a rule, applied after the rules that are already configured
+    Add(IncludeRewriteAddArgs),
+    /// Remove a rule by its index in the list
+    Remove(IncludeRewriteRemoveArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct IncludeRewriteAddArgs {
+    /// Text to look for in the shared files of cached packages
+    pub pattern: String,
+    /// What the pattern is replaced with
+    pub replacement: String,
+    /// Only rewrite the package with this id
+    #[clap(long)]
+    pub id: Option<String>,
+    /// Only rewrite versions of the package in this range
+    #[clap(long = "versionRange")]
+    pub version_range: Option<VersionReq>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct IncludeRewriteRemoveArgs {
+    /// Index of the rule, as shown when listing them
+    pub index: usize,
+}
+
+pub fn execute_include_rewrite_config_operation(
+    config: &mut AppConfig,
+    operation: IncludeRewrite,
+    local: bool,
+) -> bool {
+    let mut rules = config.include_rewrites.clone().unwrap_or_default();
+    let changed = match operation.op {
+        Some(IncludeRewriteOperation::Add(a)) => {
+            let rule = AppIncludeRewrite {
+                pattern: a.pattern,
+                replacement: a.replacement,
+                id: a.id,
+                version_range: a.version_range,
+            };
+            if rules.contains(&rule) {
+                println!("This rule was already configured!");
+                return false;
+            }
+
+            println!("Added rule {}", describe(&rule));
+            rules.push(rule);
+            true
+        }
+        Some(IncludeRewriteOperation::Remove(r)) => {
+            if r.index >= rules.len() {
+                println!("There is no rule with index {}!", r.index.bright_red());
+                return false;
+            }
+
+            println!("Removed rule {}", describe(&rules.remove(r.index)));
+            true
+        }
+        None => {
+            if rules.is_empty() {
+                println!("No include rewrite rules are configured!");
+            } else {
+                println!("Configured include rewrite rules, in the order they are applied:");
+            }
+
+            for (index, rule) in rules.iter().enumerate() {
+                println!(" {}: {}", index, describe(rule));
+            }
+            false
+        }
+    };
+
+    if changed {
+        config.include_rewrites = if rules.is_empty() { None } else { Some(rules) };
+        if local {
+            println!(
+                "Local rules are applied to this project's dependencies on the next {}, the cache is left alone",
+                "qpm restore".bright_yellow()
+            );
+        } else {
+            println!(
+                "Run {} to apply the rules to packages that are already cached",
+                "qpm cache rewrite".bright_yellow()
+            );
+        }
+    }
+    changed
+}
+
+fn describe(rule: &AppIncludeRewrite) -> String {
+    let mut description = format!(
+        "{} -> {}",
+        rule.pattern.bright_yellow(),
+        rule.replacement.bright_yellow()
+    );
+    if let Some(id) = &rule.id {
+        description.push_str(&format!(" for {}", id.bright_red()));
+    }
+    if let Some(range) = &rule.version_range {
+        description.push_str(&format!(" in {}", range.bright_green()));
+    }
+    description
+}
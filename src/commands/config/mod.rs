@@ -1,7 +1,15 @@
 use clap::{Subcommand, Args};
 
+mod ca_certificates;
 mod cache;
+mod git;
+mod git_host;
+mod include_rewrite;
 mod ndkpath;
+mod proxy;
+mod registry;
+mod retries;
+mod submodules;
 mod symlink;
 mod timeout;
 mod token;
@@ -28,14 +36,30 @@ pub enum ConfigOperation {
     Cache(cache::Cache),
     /// Enable or disable symlink usage
     Symlink(symlink::Symlink),
-    /// Get or set the timeout for web requests
+    /// Enable or disable cloning sources with git, when disabled archives are downloaded instead
+    Git(git::Git),
+    /// List, set or remove what kind of server self hosted git hosts are
+    GitHost(git_host::GitHost),
+    /// Get or set how submodules of dependency repos are fetched
+    Submodules(submodules::Submodules),
+    /// Get or set the timeouts for web requests
     Timeout(timeout::Timeout),
-    /// Get or set the github token used for restore
+    /// Get or set how many times web requests and clones are attempted
+    Retries(retries::Retries),
+    /// Get or set the proxy used for web requests and git clones
+    Proxy(proxy::Proxy),
+    /// List, add or remove extra root certificates trusted for web requests
+    CaCertificates(ca_certificates::CaCertificates),
+    /// Get, set, list or delete the tokens used for git hosts and registries
     Token(token::Token),
     /// Print the location of the global config
     Location,
     /// Get or set the ndk path used in generation of build files
     NDKPath(ndkpath::NDKPath),
+    /// List, add or remove the registries packages are looked up on
+    Registry(registry::Registry),
+    /// List, add or remove the rules that rewrite includes in cached packages
+    IncludeRewrite(include_rewrite::IncludeRewrite),
 }
 
 pub fn execute_config_operation(operation: Config) {
@@ -53,9 +77,25 @@ pub fn execute_config_operation(operation: Config) {
         ConfigOperation::Symlink(s) => {
             changed_any = symlink::execute_symlink_config_operation(&mut config, s)
         }
+        ConfigOperation::Git(g) => changed_any = git::execute_git_config_operation(&mut config, g),
+        ConfigOperation::GitHost(h) => {
+            changed_any = git_host::execute_git_host_config_operation(&mut config, h)
+        }
+        ConfigOperation::Submodules(s) => {
+            changed_any = submodules::execute_submodules_config_operation(&mut config, s)
+        }
         ConfigOperation::Timeout(t) => {
             changed_any = timeout::execute_timeout_config_operation(&mut config, t)
         }
+        ConfigOperation::Retries(r) => {
+            changed_any = retries::execute_retries_config_operation(&mut config, r)
+        }
+        ConfigOperation::Proxy(p) => {
+            changed_any = proxy::execute_proxy_config_operation(&mut config, p)
+        }
+        ConfigOperation::CaCertificates(c) => {
+            changed_any = ca_certificates::execute_ca_certificates_config_operation(&mut config, c)
+        }
         ConfigOperation::Token(t) => token::execute_token_config_operation(t),
         ConfigOperation::Location => println!(
             "Global Config is located at {}",
@@ -64,6 +104,16 @@ pub fn execute_config_operation(operation: Config) {
         ConfigOperation::NDKPath(p) => {
             changed_any = ndkpath::execute_ndk_config_operation(&mut config, p)
         }
+        ConfigOperation::Registry(r) => {
+            changed_any = registry::execute_registry_config_operation(&mut config, r)
+        }
+        ConfigOperation::IncludeRewrite(i) => {
+            changed_any = include_rewrite::execute_include_rewrite_config_operation(
+                &mut config,
+                i,
+                operation.local,
+            )
+        }
     }
 
     if !changed_any {
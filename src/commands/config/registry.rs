@@ -0,0 +1,106 @@
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+
+use crate::{
+    data::config::{Config as AppConfig, Registry as AppRegistry},
+    utils::progress,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Registry {
+    #[clap(subcommand)]
+    pub op: Option<RegistryOperation>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum RegistryOperation {
+    /// Add a registry, by default with the lowest priority
+    Add(RegistryAddArgs),
+    /// Remove a registry
+    Remove(RegistryRemoveArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RegistryAddArgs {
+    /// Base url of the registry
+    pub url: String,
+    /// Only look up package ids starting with this prefix on this registry, can be given multiple times
+    #[clap(long = "scope")]
+    pub scopes: Vec<String>,
+    /// Position to insert the registry at, 0 being the highest priority
+    #[clap(long)]
+    pub index: Option<usize>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RegistryRemoveArgs {
+    /// Base url of the registry
+    pub url: String,
+}
+
+pub fn execute_registry_config_operation(config: &mut AppConfig, operation: Registry) -> bool {
+    match operation.op {
+        Some(RegistryOperation::Add(a)) => {
+            let url = a.url.trim_end_matches('/').to_string();
+            let mut registries = config.registries();
+            if registries.iter().any(|r| r.url == url) {
+                progress::println(format!(
+                    "Registry {} was already configured!",
+                    url.bright_yellow()
+                ));
+                return false;
+            }
+
+            let registry = AppRegistry {
+                url,
+                scopes: if a.scopes.is_empty() {
+                    None
+                } else {
+                    Some(a.scopes)
+                },
+            };
+            progress::println(format!("Added registry {}", registry.url.bright_yellow()));
+            let index = a.index.unwrap_or(registries.len()).min(registries.len());
+            registries.insert(index, registry);
+            config.registries = Some(registries);
+            true
+        }
+        Some(RegistryOperation::Remove(r)) => {
+            let url = r.url.trim_end_matches('/');
+            let mut registries = config.registries();
+            let len = registries.len();
+            registries.retain(|registry| registry.url != url);
+            if registries.len() == len {
+                progress::println(format!(
+                    "Registry {} was not configured!",
+                    url.bright_yellow()
+                ));
+                return false;
+            }
+
+            progress::println(format!("Removed registry {}", url.bright_yellow()));
+            config.registries = Some(registries);
+            true
+        }
+        None => {
+            if config.registries.is_none() {
+                println!("No registries are configured, the default will be used:");
+            } else {
+                println!("Configured registries, in order of priority:");
+            }
+
+            for registry in config.registries().iter() {
+                if let Some(scopes) = &registry.scopes {
+                    progress::println(format!(
+                        " - {} (scopes: {})",
+                        registry.url.bright_yellow(),
+                        scopes.join(", ").bright_green()
+                    ));
+                } else {
+                    progress::println(format!(" - {}", registry.url.bright_yellow()));
+                }
+            }
+            false
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::data::config::Config as AppConfig;
+
+#[derive(Args, Debug, Clone)]
+pub struct Retries {
+    /// How many times a web request or clone is attempted before giving up
+    pub retries: Option<u32>,
+}
+
+pub fn execute_retries_config_operation(config: &mut AppConfig, operation: Retries) -> bool {
+    if let Some(retries) = operation.retries {
+        println!("Set attempts to {}!", retries.bright_yellow());
+        config.retries = Some(retries);
+        true
+    } else if let Some(retries) = config.retries {
+        println!(
+            "Current configured attempts is set to: {}",
+            retries.bright_yellow()
+        );
+        false
+    } else {
+        println!("Attempts are not configured!");
+        false
+    }
+}
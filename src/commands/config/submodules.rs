@@ -0,0 +1,81 @@
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::{data::config::Config as AppConfig, utils::toggle::Toggle};
+
+#[derive(Args, Debug, Clone)]
+pub struct Submodules {
+    /// Whether submodules of dependency repos are fetched, pass enable or disable
+    #[clap(long)]
+    pub fetch: Option<Toggle>,
+    /// Fetch the full history of submodules instead of only the checked out commit, pass enable or disable
+    #[clap(long)]
+    pub full_history: Option<Toggle>,
+    /// Only fetch the submodules at this path, can be given multiple times
+    #[clap(long = "path")]
+    pub paths: Vec<String>,
+    /// Fetch all submodules again, instead of only the configured paths
+    #[clap(long)]
+    pub all_paths: bool,
+}
+
+pub fn execute_submodules_config_operation(config: &mut AppConfig, operation: Submodules) -> bool {
+    let mut submodules = config.submodules.clone().unwrap_or_default();
+    let mut changed = false;
+
+    if let Some(fetch) = operation.fetch {
+        let fetch: bool = fetch.into();
+        println!("Set submodule fetching to {}", fetch.bright_yellow());
+        submodules.enabled = Some(fetch);
+        changed = true;
+    }
+    if let Some(full_history) = operation.full_history {
+        let full_history: bool = full_history.into();
+        println!(
+            "Set submodule full history to {}",
+            full_history.bright_yellow()
+        );
+        submodules.full_history = Some(full_history);
+        changed = true;
+    }
+    if operation.all_paths {
+        println!("Submodules at all paths will be fetched");
+        submodules.paths = None;
+        changed = true;
+    } else if !operation.paths.is_empty() {
+        println!(
+            "Only submodules at {} will be fetched",
+            operation.paths.join(", ").bright_yellow()
+        );
+        submodules.paths = Some(operation.paths);
+        changed = true;
+    }
+
+    if changed {
+        config.submodules = if submodules == Default::default() {
+            None
+        } else {
+            Some(submodules)
+        };
+        return true;
+    }
+
+    if config.submodules.is_none() {
+        println!("Submodule handling is not configured, all submodules are fetched shallowly!");
+        return false;
+    }
+
+    println!(
+        "Submodules are fetched: {}",
+        submodules.is_enabled().bright_yellow()
+    );
+    println!(
+        "Full history: {}",
+        submodules.is_full_history().bright_yellow()
+    );
+    match &submodules.paths {
+        Some(paths) => println!("Paths: {}", paths.join(", ").bright_yellow()),
+        None => println!("Paths: {}", "all".bright_yellow()),
+    }
+    false
+}
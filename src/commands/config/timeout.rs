@@ -6,21 +6,36 @@ use crate::data::config::Config as AppConfig;
 #[derive(Args, Debug, Clone)]
 pub struct Timeout {
     pub timeout: Option<u64>,
+    /// Get or set the connect timeout instead of the timeout for answers to api requests
+    #[clap(long, conflicts_with = "download")]
+    pub connect: bool,
+    /// Get or set the timeout used for large downloads, for the answer and then for each chunk of data
+    #[clap(long)]
+    pub download: bool,
 }
 
 pub fn execute_timeout_config_operation(config: &mut AppConfig, operation: Timeout) -> bool {
+    let (name, value) = if operation.connect {
+        ("connect timeout", &mut config.connect_timeout)
+    } else if operation.download {
+        ("download timeout", &mut config.download_timeout)
+    } else {
+        ("timeout", &mut config.timeout)
+    };
+
     if let Some(timeout) = operation.timeout {
-        println!("Set timeout to {}!", timeout.bright_yellow());
-        config.timeout = Some(timeout);
+        println!("Set {} to {}!", name, timeout.bright_yellow());
+        *value = Some(timeout);
         true
-    } else if let Some(timeout) = config.timeout {
+    } else if let Some(timeout) = value {
         println!(
-            "Current configured timeout is set to: {}",
+            "Current configured {} is set to: {}",
+            name,
             timeout.bright_yellow()
         );
         false
     } else {
-        println!("Timeout is not configured!");
+        println!("{} is not configured!", name);
         false
     }
 }
@@ -1,45 +1,135 @@
-use clap::{Args};
+use clap::Args;
 use owo_colors::OwoColorize;
 
-use crate::data::config::get_keyring;
+use crate::data::config::{
+    get_keyring, get_keyring_for, get_token, normalize_host, token_env_var, Config as AppConfig,
+};
 
 #[derive(Args, Debug, Clone)]
 pub struct Token {
     pub token: Option<String>,
+    /// The host the token is for, like gitlab.com or the host of a registry, defaults to github.com
+    #[clap(long)]
+    pub host: Option<String>,
     #[clap(long)]
     pub delete: bool,
+    /// List the hosts a token is configured for
+    #[clap(long)]
+    pub list: bool,
 }
 
 pub fn execute_token_config_operation(operation: Token) {
-    if operation.delete && get_keyring().get_password().is_ok() {
-        get_keyring()
-            .delete_password()
-            .expect("Removing password failed");
-        println!("Deleted github token from config, it will no longer be used");
+    if operation.list {
+        list_tokens();
         return;
-    } else if operation.delete {
-        println!("There was no github token configured, did not delete it");
+    }
+
+    let host = normalize_host(operation.host.as_deref().unwrap_or("github.com"));
+    // tokens live in the keyring for the whole machine, so which hosts have one is kept in the global config
+    let mut config = AppConfig::read();
+    let mut token_hosts = config.token_hosts.clone().unwrap_or_default();
+
+    if operation.delete {
+        let mut deleted = get_keyring_for(&host).delete_password().is_ok();
+        if host == "github.com" && get_keyring().get_password().is_ok() {
+            deleted |= get_keyring().delete_password().is_ok();
+        }
+
+        if deleted {
+            println!(
+                "Deleted token for {} from config, it will no longer be used",
+                host.bright_yellow()
+            );
+        } else {
+            println!(
+                "There was no token configured for {}, did not delete it",
+                host.bright_yellow()
+            );
+        }
+
+        if token_hosts.contains(&host) {
+            token_hosts.retain(|h| h != &host);
+            config.token_hosts = if token_hosts.is_empty() {
+                None
+            } else {
+                Some(token_hosts)
+            };
+            config.write();
+        }
         return;
     }
 
     if let Some(token) = operation.token {
         // write token
-        get_keyring()
+        get_keyring_for(&host)
             .set_password(&token)
             .expect("Storing token failed!");
-        println!("Configured a github token! This will now be used in qpm restore");
+        println!(
+            "Configured a token for {}! This will now be used in qpm restore and publish",
+            host.bright_yellow()
+        );
+
+        if !token_hosts.contains(&host) {
+            token_hosts.push(host);
+            config.token_hosts = Some(token_hosts);
+            config.write();
+        }
     } else {
         // read token, possibly unused so prepend with _ to prevent warnings
-        if let Ok(_token) = get_keyring().get_password() {
+        if let Some(_token) = get_token(&host) {
             #[cfg(debug_assertions)]
-            println!("Configured github token: {}", _token.bright_yellow());
+            println!(
+                "Configured token for {}: {}",
+                host.bright_yellow(),
+                _token.bright_yellow()
+            );
             #[cfg(not(debug_assertions))]
             println!(
-                "In release builds you {} view the configured github token, a token was configured though!",
-                "cannot".bright_red()
+                "In release builds you {} view the configured token, a token was configured for {} though!",
+                "cannot".bright_red(),
+                host.bright_yellow()
+            );
+        } else {
+            println!(
+                "No token was configured for {}, or getting the token failed!",
+                host.bright_yellow()
+            );
+        }
+    }
+}
+
+fn list_tokens() {
+    let mut hosts = AppConfig::read().token_hosts.unwrap_or_default();
+    if get_keyring().get_password().is_ok() && !hosts.iter().any(|h| h == "github.com") {
+        hosts.insert(0, "github.com".to_string());
+    }
+
+    let from_env: Vec<String> = std::env::vars()
+        .map(|(name, _)| name)
+        .filter(|name| name.starts_with("QPM_TOKEN_"))
+        .collect();
+
+    if hosts.is_empty() && from_env.is_empty() {
+        println!("No tokens are configured!");
+        return;
+    }
+
+    for host in hosts.iter() {
+        if from_env.contains(&token_env_var(host)) {
+            println!(
+                " - {} (overridden by {})",
+                host.bright_yellow(),
+                token_env_var(host).bright_green()
             );
         } else {
-            println!("No token was configured, or getting the token failed!");
+            println!(" - {}", host.bright_yellow());
         }
     }
+
+    for name in from_env
+        .iter()
+        .filter(|name| !hosts.iter().any(|h| &&token_env_var(h) == name))
+    {
+        println!(" - set by {}", name.bright_green());
+    }
 }
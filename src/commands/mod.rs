@@ -7,4 +7,6 @@ pub mod list;
 pub mod package;
 pub mod publish;
 pub mod qmod;
+pub mod registry;
 pub mod restore;
+pub mod vendor;
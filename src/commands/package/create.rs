@@ -78,6 +78,9 @@ pub fn package_create_operation(create_parameters: PackageOperationCreateArgs) {
         dependencies_dir: Path::new("extern").to_owned(),
         dependencies: Vec::<Dependency>::default(),
         additional_data: AdditionalDependencyData::default(),
+        vendor_dir: None,
+        include_rewrites: None,
+        patches: None,
     };
 
     package.write();
@@ -1,7 +1,10 @@
 use clap::{Args};
 use semver::Version;
 
-use crate::data::package::{PackageConfig, SharedPackageConfig};
+use crate::{
+    data::package::{PackageConfig, SharedPackageConfig},
+    utils::progress,
+};
 
 #[derive(Args, Debug, Clone)]
 
@@ -63,7 +66,7 @@ fn package_set_name(package: &mut PackageConfig, name: String) {
 }
 
 fn package_set_url(package: &mut PackageConfig, url: String) {
-    println!("Setting package url: {}", url);
+    progress::println(format!("Setting package url: {}", url));
     package.info.url = Option::Some(url);
 }
 
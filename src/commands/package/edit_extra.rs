@@ -12,6 +12,14 @@ pub struct EditExtraArgs {
     #[clap(long = "branchName")]
     pub branch_name: Option<String>,
 
+    /// Change the tag in additional data, cloned instead of the branch
+    #[clap(long = "tag")]
+    pub tag: Option<String>,
+
+    /// Change the commit in additional data, checked out instead of the tag or branch
+    #[clap(long = "commit")]
+    pub commit: Option<String>,
+
     /// Change the headers only bool in additional data, pass enable or disable
     #[clap(long = "headersOnly")]
     pub headers_only: Option<Toggle>,
@@ -44,6 +52,18 @@ pub struct EditExtraArgs {
     #[clap(long = "subFolder")]
     pub sub_folder: Option<String>,
 
+    /// Whether the submodules of the repo are fetched, pass enable or disable
+    #[clap(long = "submodules")]
+    pub submodules: Option<Toggle>,
+
+    /// Provide a submodule path to only fetch that submodule, prepend with - to remove an entry
+    #[clap(long = "submodulePath")]
+    pub submodule_path: Option<String>,
+
+    /// Fetch the full history of submodules instead of only the checked out commit, pass enable or disable
+    #[clap(long = "submodulesFullHistory")]
+    pub submodules_full_history: Option<Toggle>,
+
     /// Additional options for compilation and edits to compilation related files.
     #[clap(subcommand)]
     pub compile_options: Option<EditExtraOptions>,
@@ -83,6 +103,14 @@ pub fn package_edit_extra_operation(edit_parameters: EditExtraArgs) {
         package_edit_extra_branch_name(&mut package, branch_name);
         any_changed = true;
     }
+    if let Some(tag) = edit_parameters.tag {
+        package_edit_extra_tag(&mut package, tag);
+        any_changed = true;
+    }
+    if let Some(commit) = edit_parameters.commit {
+        package_edit_extra_commit(&mut package, commit);
+        any_changed = true;
+    }
     if let Some(headers_only) = edit_parameters.headers_only {
         package_edit_extra_headers_only(&mut package, headers_only.into());
         any_changed = true;
@@ -115,6 +143,18 @@ pub fn package_edit_extra_operation(edit_parameters: EditExtraArgs) {
         package_edit_extra_sub_folder(&mut package, sub_folder);
         any_changed = true;
     }
+    if let Some(submodules) = edit_parameters.submodules {
+        package_edit_extra_submodules(&mut package, submodules.into());
+        any_changed = true;
+    }
+    if let Some(submodule_path) = edit_parameters.submodule_path {
+        package_edit_extra_submodule_path(&mut package, submodule_path);
+        any_changed = true;
+    }
+    if let Some(full_history) = edit_parameters.submodules_full_history {
+        package_edit_extra_submodules_full_history(&mut package, full_history.into());
+        any_changed = true;
+    }
 
     if any_changed {
         package.write();
@@ -133,6 +173,16 @@ pub fn package_edit_extra_branch_name(package: &mut PackageConfig, branch_name:
     package.info.additional_data.branch_name = Some(branch_name);
 }
 
+pub fn package_edit_extra_tag(package: &mut PackageConfig, tag: String) {
+    println!("Setting tag: {:#?}", tag);
+    package.info.additional_data.tag = Some(tag);
+}
+
+pub fn package_edit_extra_commit(package: &mut PackageConfig, commit: String) {
+    println!("Setting commit: {:#?}", commit);
+    package.info.additional_data.commit = Some(commit);
+}
+
 pub fn package_edit_extra_headers_only(package: &mut PackageConfig, headers_only: bool) {
     println!("Setting headers_only: {:#?}", headers_only);
     package.info.additional_data.headers_only = Some(headers_only);
@@ -199,3 +249,41 @@ pub fn package_edit_extra_sub_folder(package: &mut PackageConfig, sub_folder: St
     println!("Setting sub_folder: {:#?}", sub_folder);
     package.info.additional_data.sub_folder = Some(sub_folder);
 }
+
+pub fn package_edit_extra_submodules(package: &mut PackageConfig, submodules: bool) {
+    println!("Setting submodules: {:#?}", submodules);
+    package
+        .info
+        .additional_data
+        .submodules
+        .get_or_insert_with(Default::default)
+        .enabled = Some(submodules);
+}
+
+pub fn package_edit_extra_submodule_path(package: &mut PackageConfig, submodule_path: String) {
+    println!("Setting submodule_path: {}", submodule_path);
+    let submodules = package
+        .info
+        .additional_data
+        .submodules
+        .get_or_insert_with(Default::default);
+    let paths = submodules.paths.get_or_insert_with(Vec::new);
+    match submodule_path.strip_prefix('-') {
+        Some(path) => paths.retain(|p| p != path),
+        None => {
+            if !paths.contains(&submodule_path) {
+                paths.push(submodule_path);
+            }
+        }
+    }
+}
+
+pub fn package_edit_extra_submodules_full_history(package: &mut PackageConfig, full_history: bool) {
+    println!("Setting submodules_full_history: {:#?}", full_history);
+    package
+        .info
+        .additional_data
+        .submodules
+        .get_or_insert_with(Default::default)
+        .full_history = Some(full_history);
+}
@@ -1,8 +1,27 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
 use owo_colors::OwoColorize;
 
-use crate::data::package::SharedPackageConfig;
-pub fn execute_publish_operation() {
-    let package = SharedPackageConfig::read();
+use crate::{
+    data::{package::SharedPackageConfig, qpackages},
+    utils::{git, hash, progress},
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Publish {
+    /// The locally built .so to hash instead of downloading soLink, it has to be the same file that was uploaded
+    #[clap(long)]
+    pub so: Option<PathBuf>,
+    /// The locally built debug .so to hash instead of downloading debugSoLink
+    #[clap(long)]
+    pub debug_so: Option<PathBuf>,
+}
+
+pub fn execute_publish_operation(operation: Publish) {
+    // dependencies have to be checked against the registries, not a vendored copy
+    qpackages::set_vendor_dir(None);
+    let mut package = SharedPackageConfig::read();
     if package.config.info.url.is_none() {
         println!("Package without url can not publish!");
         return;
@@ -63,6 +82,23 @@ pub fn execute_publish_operation() {
 
     // TODO: Implement a check that gets the repo and checks if the shared folder and subfolder exists, if not it throws an error and won't let you publish
 
+    // declare the hashes of the artifacts, so consumers don't have to trust whatever they download first
+    let additional_data = &mut package.config.info.additional_data;
+    if let Some(so_link) = &additional_data.so_link {
+        additional_data.so_sha256 = Some(hash_artifact(so_link, operation.so.as_deref()));
+    }
+    if let Some(debug_so_link) = &additional_data.debug_so_link {
+        additional_data.debug_so_sha256 =
+            Some(hash_artifact(debug_so_link, operation.debug_so.as_deref()));
+    }
+    let url = package.config.info.url.as_ref().unwrap();
+    additional_data.source_sha256 = if git::is_repo_url(url) {
+        // cloned, not downloaded as an archive
+        None
+    } else {
+        Some(hash_artifact(url, None))
+    };
+
     package.publish();
 
     println!(
@@ -70,3 +106,19 @@ pub fn execute_publish_operation() {
         package.config.info.id, package.config.info.version
     );
 }
+
+/// Hashes the local file if given, otherwise downloads the link and hashes that
+fn hash_artifact(link: &str, local: Option<&Path>) -> String {
+    if let Some(local) = local {
+        println!("Hashing {}", local.display().bright_yellow());
+        return hash::sha256_file(local);
+    }
+
+    progress::println(format!("Downloading {} to hash it", link.bright_yellow()));
+    let path = std::env::temp_dir().join(format!("qpm_publish_{}", std::process::id()));
+    git::get_release(link.to_string(), &path);
+
+    let hash = hash::sha256_file(&path);
+    std::fs::remove_file(&path).ok();
+    hash
+}
@@ -0,0 +1,300 @@
+use std::{
+    io::{Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+use semver::Version;
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use crate::data::{package::SharedPackageConfig, qpackages::PackageVersion};
+
+#[derive(Args, Debug, Clone)]
+pub struct Registry {
+    #[clap(subcommand)]
+    pub op: RegistryOperation,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum RegistryOperation {
+    /// Serve a folder of package metadata as a qpackages compatible registry
+    Serve(ServeArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ServeArgs {
+    /// The folder to serve, packages are stored as <folder>/<id>/<version>.json
+    #[clap(default_value = ".")]
+    pub path: PathBuf,
+    /// The address to listen on
+    #[clap(long, default_value = "127.0.0.1")]
+    pub host: String,
+    /// The port to listen on
+    #[clap(short, long, default_value_t = 8080)]
+    pub port: u16,
+    /// Also serve the files in this folder under /artifacts/
+    #[clap(long)]
+    pub artifacts: Option<PathBuf>,
+    /// Only accept published packages with this exact Authorization header
+    #[clap(long)]
+    pub auth: Option<String>,
+}
+
+pub fn execute_registry_operation(operation: Registry) {
+    match operation.op {
+        RegistryOperation::Serve(s) => serve(s),
+    }
+}
+
+fn serve(args: ServeArgs) {
+    std::fs::create_dir_all(&args.path).expect("Failed to create registry folder");
+    let address = format!("{}:{}", args.host, args.port);
+    let server = Server::http(&address)
+        .unwrap_or_else(|e| panic!("Failed to listen on {}: {}", address.bright_yellow(), e));
+
+    println!(
+        "Serving registry {} on {}",
+        args.path.display().bright_yellow(),
+        format!("http://{}", address).bright_green()
+    );
+
+    for mut request in server.incoming_requests() {
+        let response = handle_request(&args, &mut request);
+        println!(
+            "{} {} -> {}",
+            request.method(),
+            request.url(),
+            response.status_code().0
+        );
+        if let Err(e) = request.respond(response) {
+            println!("Failed to respond: {}", e.bright_red());
+        }
+    }
+}
+
+fn handle_request(args: &ServeArgs, request: &mut Request) -> ResponseBox {
+    // queries like ?limit=0 don't change anything for us
+    let url = request.url().split('?').next().unwrap_or("").to_string();
+    let segments: Vec<&str> = url
+        .trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["artifacts", rest @ ..]) if args.artifacts.is_some() => {
+            serve_artifact(args.artifacts.as_ref().unwrap(), rest, request)
+        }
+        (Method::Get, []) => json_response(&list_packages(&args.path)),
+        (Method::Get, [id]) => match list_versions(&args.path, id) {
+            Some(versions) => json_response(&versions),
+            None => status_response(404, "package not found"),
+        },
+        (Method::Get, [id, version]) => match package_path(&args.path, id, version) {
+            Some(path) if path.is_file() => file_response(&path, "application/json"),
+            _ => status_response(404, "version not found"),
+        },
+        (Method::Post, [id, version]) => publish(args, request, id, version),
+        _ => status_response(404, "not found"),
+    }
+}
+
+/// All package ids that have at least one version
+fn list_packages(root: &Path) -> Vec<String> {
+    let mut ids: Vec<String> = std::fs::read_dir(root)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|id| list_versions(root, id).map_or(false, |v| !v.is_empty()))
+                .collect()
+        })
+        .unwrap_or_default();
+    ids.sort();
+    ids
+}
+
+/// All versions of a package, newest first like qpackages returns them
+fn list_versions(root: &Path, id: &str) -> Option<Vec<PackageVersion>> {
+    if !is_safe_segment(id) {
+        return None;
+    }
+
+    let mut versions: Vec<PackageVersion> = std::fs::read_dir(root.join(id))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let version = name.strip_suffix(".json")?.parse::<Version>().ok()?;
+            Some(PackageVersion {
+                id: id.to_string(),
+                version,
+            })
+        })
+        .collect();
+
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+    Some(versions)
+}
+
+fn package_path(root: &Path, id: &str, version: &str) -> Option<PathBuf> {
+    let version = version.parse::<Version>().ok()?;
+    if !is_safe_segment(id) {
+        return None;
+    }
+
+    Some(root.join(id).join(format!("{}.json", version)))
+}
+
+fn publish(args: &ServeArgs, request: &mut Request, id: &str, version: &str) -> ResponseBox {
+    if let Some(auth) = &args.auth {
+        let authorized = request
+            .headers()
+            .iter()
+            .any(|h| h.field.equiv("Authorization") && h.value.as_str() == auth);
+        if !authorized {
+            return status_response(401, "unauthorized");
+        }
+    }
+
+    let path = match package_path(&args.path, id, version) {
+        Some(path) => path,
+        None => return status_response(400, "invalid id or version"),
+    };
+
+    let mut body = String::new();
+    if request.as_reader().read_to_string(&mut body).is_err() {
+        return status_response(400, "could not read body");
+    }
+
+    let package = match serde_json::from_str::<SharedPackageConfig>(&body) {
+        Ok(package) => package,
+        Err(e) => return status_response(400, &format!("invalid package: {}", e)),
+    };
+
+    if package.config.info.id != id || package.config.info.version.to_string() != version {
+        return status_response(400, "package id or version does not match the url");
+    }
+
+    if let Err(e) = write_package(&path, &package) {
+        println!(
+            "Failed to store {} v{}: {}",
+            id.bright_red(),
+            version.bright_green(),
+            e.bright_red()
+        );
+        return status_response(500, "could not store the package");
+    }
+    println!("Published {} v{}", id.bright_red(), version.bright_green());
+
+    status_response(200, "published")
+}
+
+fn write_package(path: &Path, package: &SharedPackageConfig) -> std::io::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(file, package)?;
+    Ok(())
+}
+
+fn serve_artifact(root: &Path, segments: &[&str], request: &Request) -> ResponseBox {
+    if segments.is_empty() || !segments.iter().all(|s| is_safe_segment(s)) {
+        return status_response(404, "not found");
+    }
+
+    let path = segments.iter().fold(root.to_path_buf(), |p, s| p.join(s));
+    if !path.is_file() {
+        return status_response(404, "not found");
+    }
+
+    let mut file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(_) => return status_response(500, "could not open the artifact"),
+    };
+    let metadata = match file.metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return status_response(500, "could not read the artifact"),
+    };
+    let len = metadata.len();
+    let etag = artifact_etag(&metadata);
+
+    // support resuming downloads with "Range: bytes=<start>-", but only of the same file as before
+    let find_header = |field: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(field))
+            .map(|h| h.value.as_str())
+    };
+    let start = find_header("Range")
+        .filter(|_| find_header("If-Range").map_or(true, |if_range| if_range == etag))
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.strip_suffix('-'))
+        .and_then(|start| start.parse::<u64>().ok());
+
+    match start {
+        Some(start) if start >= len => status_response(416, "range not satisfiable"),
+        Some(start) => {
+            if file.seek(SeekFrom::Start(start)).is_err() {
+                return status_response(500, "could not read the artifact");
+            }
+            Response::empty(206)
+                .with_data(file, Some((len - start) as usize))
+                .with_header(header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, len - 1, len),
+                ))
+                .with_header(header("Content-Type", "application/octet-stream"))
+                .with_header(header("ETag", &etag))
+                .boxed()
+        }
+        None => Response::from_file(file)
+            .with_header(header("Content-Type", "application/octet-stream"))
+            .with_header(header("ETag", &etag))
+            .boxed(),
+    }
+}
+
+/// A strong etag from the size and modification time, which change whenever an artifact is replaced
+fn artifact_etag(metadata: &std::fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+/// path segments from urls may not escape the served folders
+fn is_safe_segment(segment: &str) -> bool {
+    let path = Path::new(segment);
+    path.components().count() == 1 && matches!(path.components().next(), Some(Component::Normal(_)))
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("Invalid header")
+}
+
+fn json_response<T: serde::Serialize>(value: &T) -> ResponseBox {
+    Response::from_string(serde_json::to_string(value).expect("Serialization failed"))
+        .with_header(header("Content-Type", "application/json"))
+        .boxed()
+}
+
+fn file_response(path: &Path, content_type: &str) -> ResponseBox {
+    match std::fs::File::open(path) {
+        Ok(file) => Response::from_file(file)
+            .with_header(header("Content-Type", content_type))
+            .boxed(),
+        // callers only get here for files that exist
+        Err(_) => status_response(500, "could not read the file"),
+    }
+}
+
+fn status_response(code: u16, message: &str) -> ResponseBox {
+    Response::from_string(message)
+        .with_status_code(code)
+        .boxed()
+}
@@ -1,14 +1,26 @@
 use std::io::Write;
 
+use clap::Args;
+
 use crate::data::{
     config::Config,
     package::{PackageConfig, SharedPackageConfig},
 };
 
-pub fn execute_restore_operation() {
+#[derive(Args, Debug, Clone)]
+pub struct Restore {
+    /// How many dependencies are downloaded at the same time, defaults to the number of cpus
+    #[clap(short, long)]
+    pub jobs: Option<usize>,
+}
+
+pub fn execute_restore_operation(operation: Restore) {
     println!("package should be restoring");
     let package = PackageConfig::read();
-    let shared_package = SharedPackageConfig::from_package(&package);
+    let mut shared_package = SharedPackageConfig::from_package(&package);
+    if std::path::Path::new("qpm.shared.json").exists() {
+        shared_package.copy_lock_data_from(&SharedPackageConfig::read());
+    }
 
     // create used dirs
     std::fs::create_dir_all("src").expect("Failed to create directory");
@@ -24,9 +36,11 @@ pub fn execute_restore_operation() {
     }
 
     shared_package.write();
-    if std::path::Path::new(&shared_package.config.dependencies_dir).exists() {
-        // HACK: qpm rust is fast enough to where removing the folder and then remaking it is doable
-        super::clear::remove_dependencies_dir();
-    }
-    shared_package.restore();
+    let jobs = operation
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |jobs| jobs.get()));
+    // only what changed since the last restore is touched, so builds don't start over
+    shared_package.restore(jobs);
+    // restoring records the checksums of anything that was new
+    shared_package.write();
 }
@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use fs_extra::dir::copy as copy_directory;
+use owo_colors::OwoColorize;
+use remove_dir_all::remove_dir_all;
+
+use crate::data::{
+    package::{PackageConfig, SharedPackageConfig},
+    qpackages,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Vendor {
+    /// The folder to copy all dependencies into, relative to the package root
+    #[clap(default_value = "vendor")]
+    pub path: PathBuf,
+}
+
+pub fn execute_vendor_operation(operation: Vendor) {
+    let mut package = PackageConfig::read();
+    if operation.path.is_absolute() {
+        panic!(
+            "Vendor path {} is absolute, pass a path inside the package instead!",
+            operation.path.display().bright_yellow()
+        );
+    }
+
+    // resolve and cache through the registries, not a previously vendored copy
+    qpackages::set_vendor_dir(None);
+    package.vendor_dir = None;
+    let mut shared_package = SharedPackageConfig::from_package(&package);
+    if std::path::Path::new("qpm.shared.json").exists() {
+        shared_package.copy_lock_data_from(&SharedPackageConfig::read());
+    }
+
+    std::fs::create_dir_all(&operation.path).expect("Failed to create vendor folder");
+    let vendor_path = operation.path.canonicalize().unwrap();
+
+    for shared_dep in shared_package.restored_dependencies.iter_mut() {
+        shared_dep.cache();
+
+        println!(
+            "Vendoring dependency {} {}",
+            shared_dep.dependency.id.bright_red(),
+            shared_dep.version.bright_green()
+        );
+
+        let cache_path = shared_dep.cache_path();
+        let vendored_path = vendor_path
+            .join(&shared_dep.dependency.id)
+            .join(shared_dep.version.to_string());
+
+        if vendored_path.exists() {
+            remove_dir_all(&vendored_path).expect("Failed to remove old vendored folder");
+        }
+
+        for folder in ["src", "lib"] {
+            let from = cache_path.join(folder);
+            if !from.exists() {
+                continue;
+            }
+
+            let to = vendored_path.join(folder);
+            std::fs::create_dir_all(&to).expect("Failed to create destination folder");
+            let mut options = fs_extra::dir::CopyOptions::new();
+            options.overwrite = true;
+            options.copy_inside = true;
+            options.content_only = true;
+            copy_directory(&from, &to, &options).expect("Failed to copy directory!");
+        }
+
+        let checksums_path = cache_path.join("checksums.json");
+        if checksums_path.exists() {
+            std::fs::copy(&checksums_path, vendored_path.join("checksums.json"))
+                .expect("Failed to copy checksums");
+        }
+
+        // the shared config is what restore resolves against instead of the registry
+        let file =
+            std::fs::File::create(vendored_path.join("qpm.shared.json")).expect("create failed");
+        serde_json::to_writer_pretty(file, &shared_dep.get_shared_package())
+            .expect("Serialization failed");
+    }
+
+    remove_stale_vendored(&vendor_path, &shared_package);
+
+    package.vendor_dir = Some(operation.path);
+    package.write();
+    shared_package.write();
+
+    println!(
+        "Vendored {} dependencies into {}",
+        shared_package.restored_dependencies.len().bright_yellow(),
+        vendor_path.display().bright_yellow()
+    );
+}
+
+/// removes any vendored package versions that are no longer part of the resolution
+fn remove_stale_vendored(vendor_path: &std::path::Path, shared_package: &SharedPackageConfig) {
+    for id_entry in std::fs::read_dir(vendor_path).unwrap().flatten() {
+        if !id_entry.path().is_dir() {
+            continue;
+        }
+
+        let id = id_entry.file_name().to_string_lossy().to_string();
+        for version_entry in std::fs::read_dir(id_entry.path()).unwrap().flatten() {
+            let version = version_entry.file_name().to_string_lossy().to_string();
+            let used = shared_package
+                .restored_dependencies
+                .iter()
+                .any(|dep| dep.dependency.id == id && dep.version.to_string() == version);
+
+            if !used {
+                println!(
+                    "Removing unused vendored package {} {}",
+                    id.bright_red(),
+                    version.bright_green()
+                );
+                remove_dir_all(version_entry.path()).expect("Failed to remove vendored folder");
+            }
+        }
+
+        if std::fs::read_dir(id_entry.path()).unwrap().next().is_none() {
+            std::fs::remove_dir(id_entry.path()).expect("Failed to remove vendored folder");
+        }
+    }
+}
@@ -5,6 +5,11 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    data::{dependency::IncludeRewrite, package::SubmoduleOptions},
+    utils::{git_host::HostKind, redact},
+};
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
@@ -12,10 +17,84 @@ pub struct Config {
     pub symlink: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache: Option<PathBuf>,
+    /// Size in megabytes qpm cache gc shrinks the cache to, by removing the least recently used packages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cache_size: Option<u64>,
+    /// Milliseconds a web request may take until it is answered, api responses like the registry's are read within the same time
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout: Option<u64>,
+    /// Connect timeout for web requests in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Milliseconds a large download, like .so files and source archives, may take until it is answered and may then wait for each next chunk of data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_timeout: Option<u64>,
+    /// How many times a web request or clone is attempted before giving up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Proxy url to send all web requests and git clones through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Extra root certificates (pem or der files) to trust for web requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_certificates: Option<Vec<PathBuf>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ndk_path: Option<String>,
+    /// Whether sources are cloned with git, otherwise (or if git isn't installed) the host's archive is downloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_git: Option<bool>,
+    /// How submodules of dependency repos are fetched, packages can override this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submodules: Option<SubmoduleOptions>,
+    /// Replacements applied to the shared files of packages to fix their includes, global ones change the cache and local ones are applied when restoring
+    ///
+    /// Not combined, see IncludeRewrite::for_cache and IncludeRewrite::for_project
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_rewrites: Option<Vec<IncludeRewrite>>,
+    /// The registries to look packages up on, in order of priority
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registries: Option<Vec<Registry>>,
+    /// Hosts that have a token in the keyring, since the keyring can't list them itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_hosts: Option<Vec<String>>,
+    /// What kind of server self hosted git hosts are, which can't always be told from their url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_hosts: Option<Vec<GitHost>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GitHost {
+    /// Host name, with the port if it isn't the default one
+    pub host: String,
+    pub kind: HostKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Registry {
+    /// Base url of the registry, e.g. https://qpackages.com
+    pub url: String,
+    /// If given, only package ids starting with one of these prefixes are looked up on this registry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+}
+
+impl Registry {
+    pub fn qpackages() -> Registry {
+        Registry {
+            url: "https://qpackages.com".to_string(),
+            scopes: None,
+        }
+    }
+
+    /// whether this registry should be asked about the package with this id
+    pub fn handles(&self, id: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|scope| id.starts_with(scope.as_str())),
+            None => true,
+        }
+    }
 }
 
 impl Default for Config {
@@ -24,8 +103,20 @@ impl Default for Config {
         Config {
             symlink: Some(true),
             cache: Some(dirs::data_dir().unwrap().join("QPM-Rust").join("cache")),
+            max_cache_size: None,
             timeout: Some(5000),
+            connect_timeout: Some(5000),
+            download_timeout: Some(60000),
+            retries: Some(3),
+            proxy: None,
+            ca_certificates: None,
             ndk_path: None,
+            use_git: None,
+            submodules: None,
+            include_rewrites: None,
+            registries: Some(vec![Registry::qpackages()]),
+            token_hosts: None,
+            git_hosts: None,
         }
     }
 }
@@ -65,8 +156,20 @@ impl Config {
             Config {
                 symlink: None,
                 cache: None,
+                max_cache_size: None,
                 timeout: None,
+                connect_timeout: None,
+                download_timeout: None,
+                retries: None,
+                proxy: None,
+                ca_certificates: None,
                 ndk_path: None,
+                use_git: None,
+                submodules: None,
+                include_rewrites: None,
+                registries: None,
+                token_hosts: None,
+                git_hosts: None,
             }
         }
     }
@@ -91,17 +194,67 @@ impl Config {
             if local_config.cache.is_some() {
                 config.cache = local_config.cache;
             }
+            if local_config.max_cache_size.is_some() {
+                config.max_cache_size = local_config.max_cache_size;
+            }
             if local_config.timeout.is_some() {
                 config.timeout = local_config.timeout;
             }
+            if local_config.connect_timeout.is_some() {
+                config.connect_timeout = local_config.connect_timeout;
+            }
+            if local_config.download_timeout.is_some() {
+                config.download_timeout = local_config.download_timeout;
+            }
+            if local_config.retries.is_some() {
+                config.retries = local_config.retries;
+            }
+            if local_config.proxy.is_some() {
+                config.proxy = local_config.proxy;
+            }
+            if local_config.ca_certificates.is_some() {
+                config.ca_certificates = local_config.ca_certificates;
+            }
             if local_config.ndk_path.is_some() {
                 config.ndk_path = local_config.ndk_path;
             }
+            if local_config.use_git.is_some() {
+                config.use_git = local_config.use_git;
+            }
+            if let Some(submodules) = local_config.submodules {
+                config.submodules = Some(submodules.or(&config.submodules.unwrap_or_default()));
+            }
+            // local registries are asked before the global ones
+            if let Some(mut registries) = local_config.registries {
+                for registry in config.registries() {
+                    if !registries.iter().any(|r| r.url == registry.url) {
+                        registries.push(registry);
+                    }
+                }
+                config.registries = Some(registries);
+            }
+            // tokens are stored for the whole machine, so token_hosts is only read from the global config
+            // local host kinds win over the global ones
+            if let Some(mut git_hosts) = local_config.git_hosts {
+                for git_host in config.git_hosts.unwrap_or_default() {
+                    if !git_hosts.iter().any(|h| h.host == git_host.host) {
+                        git_hosts.push(git_host);
+                    }
+                }
+                config.git_hosts = Some(git_hosts);
+            }
         }
 
         config
     }
 
+    /// the configured registries, or just qpackages.com if none are configured
+    pub fn registries(&self) -> Vec<Registry> {
+        self.registries
+            .clone()
+            .unwrap_or_else(|| vec![Registry::qpackages()])
+    }
+
     pub fn write(&self) {
         let config = serde_json::to_string_pretty(&self).expect("Serialization failed");
         let path = Config::global_config_path();
@@ -131,7 +284,62 @@ impl Config {
     }
 }
 
+/// The keyring entry tokens were stored in before they were per host, always a github token
 #[inline]
 pub fn get_keyring() -> keyring::Keyring<'static> {
     keyring::Keyring::new("qpm", "github")
 }
+
+/// The keyring entry holding the token for a host, like github.com or the host of a registry
+#[inline]
+pub fn get_keyring_for(host: &str) -> keyring::Keyring<'_> {
+    keyring::Keyring::new("qpm", host)
+}
+
+/// Turns whatever the user passed as a host, possibly a full url, into just the host (and port)
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = host.split_once("://").map_or(host, |(_, rest)| rest);
+    let host = host.split('/').next().unwrap_or(host);
+    // drop any credentials that were in the url
+    let host = host.rsplit_once('@').map_or(host, |(_, rest)| rest);
+    host.to_lowercase()
+}
+
+/// The environment variable that overrides the token of a host, e.g. QPM_TOKEN_GITHUB_COM
+pub fn token_env_var(host: &str) -> String {
+    let name: String = normalize_host(host)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("QPM_TOKEN_{}", name)
+}
+
+/// The token for a host, from its environment variable if set, otherwise from the keyring
+///
+/// Every token handed out is registered for redaction, so it can't end up in any output
+pub fn get_token(host: &str) -> Option<String> {
+    let host = normalize_host(host);
+    let token = std::env::var(token_env_var(&host))
+        .ok()
+        .filter(|token| !token.is_empty())
+        .or_else(|| get_keyring_for(&host).get_password().ok())
+        .or_else(|| {
+            if host == "github.com" {
+                get_keyring().get_password().ok()
+            } else {
+                None
+            }
+        });
+
+    if let Some(token) = &token {
+        redact::register(token);
+    }
+    token
+}
@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use owo_colors::OwoColorize;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::{data::package::AdditionalPackageData, utils::hash::sha256_file};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtifactChecksums {
+    /// sha256 of the release .so or .a file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub so: Option<String>,
+
+    /// sha256 of the debug .so or .a file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_so: Option<String>,
+
+    /// sha256 of the downloaded source archive, not set for sources that were cloned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl ArtifactChecksums {
+    /// Reads the checksums stored in a cache entry, and hashes the libs that are in it
+    pub fn of_cache_entry(base_path: &Path, so_name: &str) -> ArtifactChecksums {
+        let mut checksums = ArtifactChecksums::read_path(&base_path.join("checksums.json"));
+
+        let so_path = base_path.join("lib").join(so_name);
+        if so_path.exists() {
+            checksums.so = Some(sha256_file(&so_path));
+        }
+
+        let debug_so_path = base_path.join("lib").join(format!("debug_{}", so_name));
+        if debug_so_path.exists() {
+            checksums.debug_so = Some(sha256_file(&debug_so_path));
+        }
+
+        checksums
+    }
+
+    /// The checksums a publisher declared in the package metadata
+    pub fn published(additional_data: &AdditionalPackageData) -> ArtifactChecksums {
+        ArtifactChecksums {
+            so: additional_data.so_sha256.clone(),
+            debug_so: additional_data.debug_so_sha256.clone(),
+            source: additional_data.source_sha256.clone(),
+        }
+    }
+
+    pub fn read_path(path: &Path) -> ArtifactChecksums {
+        match std::fs::File::open(path) {
+            Ok(file) => serde_json::from_reader(file).expect("Deserializing checksums failed"),
+            Err(_) => ArtifactChecksums::default(),
+        }
+    }
+
+    pub fn write_path(&self, path: &Path) {
+        let file = std::fs::File::create(path).expect("create failed");
+        serde_json::to_writer_pretty(file, &self).expect("Serialization failed");
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.so.is_none() && self.debug_so.is_none() && self.source.is_none()
+    }
+
+    /// Fills in any checksums we don't know yet from other
+    pub fn merge_missing(&mut self, other: &ArtifactChecksums) {
+        if self.so.is_none() {
+            self.so = other.so.clone();
+        }
+        if self.debug_so.is_none() {
+            self.debug_so = other.debug_so.clone();
+        }
+        if self.source.is_none() {
+            self.source = other.source.clone();
+        }
+    }
+
+    /// Panics if any artifact self knows a checksum for differs or is gone from actual, `expected_from` says where self came from
+    pub fn verify(
+        &self,
+        actual: &ArtifactChecksums,
+        expected_from: &str,
+        id: &str,
+        version: &Version,
+    ) {
+        let pairs = [
+            ("so", &self.so, &actual.so),
+            ("debug so", &self.debug_so, &actual.debug_so),
+            ("source archive", &self.source, &actual.source),
+        ];
+
+        for (artifact, expected, got) in pairs {
+            if let Some(expected) = expected {
+                // a missing artifact is as unexpected as a different one, restoring without it would go unnoticed
+                ArtifactChecksums::verify_artifact(
+                    artifact,
+                    expected,
+                    got.as_deref().unwrap_or("missing"),
+                    expected_from,
+                    id,
+                    version,
+                );
+            }
+        }
+    }
+
+    /// Panics if the checksum of a single artifact is not the expected one, `expected_from` says where expected came from
+    pub fn verify_artifact(
+        artifact: &str,
+        expected: &str,
+        got: &str,
+        expected_from: &str,
+        id: &str,
+        version: &Version,
+    ) {
+        if expected != got {
+            panic!(
+                "Checksum mismatch for the {} of {} {}!\nexpected: {}\ngot:      {}\nThe artifact is not the one declared in {}, if the cache got corrupted run {}, if you trust the new artifact run {} and restore again",
+                artifact,
+                id.bright_red(),
+                version.bright_green(),
+                expected.bright_green(),
+                got.bright_red(),
+                expected_from,
+                "qpm cache clear".bright_yellow(),
+                "qpm clear".bright_yellow()
+            );
+        }
+    }
+}
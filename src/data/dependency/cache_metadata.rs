@@ -0,0 +1,87 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheMetadata {
+    pub id: String,
+    pub version: Version,
+    /// The git repo or archive the sources came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// The commit that was checked out, only for git sources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub so_link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_so_link: Option<String>,
+    /// When the sources were downloaded, in seconds since the unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_downloaded: Option<u64>,
+    /// When the .so was downloaded, in seconds since the unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub so_downloaded: Option<u64>,
+    /// When the debug .so was downloaded, in seconds since the unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_so_downloaded: Option<u64>,
+    /// Size of the whole entry on disk in bytes, as of the last download
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl CacheMetadata {
+    pub fn new(id: &str, version: &Version) -> CacheMetadata {
+        CacheMetadata {
+            id: id.to_string(),
+            version: version.clone(),
+            source_url: None,
+            branch: None,
+            commit: None,
+            so_link: None,
+            debug_so_link: None,
+            source_downloaded: None,
+            so_downloaded: None,
+            debug_so_downloaded: None,
+            size: None,
+        }
+    }
+
+    /// The metadata file in a cache entry, None for entries cached before it was written
+    pub fn read_path(base_path: &Path) -> Option<CacheMetadata> {
+        let file = std::fs::File::open(base_path.join("metadata.json")).ok()?;
+        serde_json::from_reader(file).ok()
+    }
+
+    pub fn write_path(&self, base_path: &Path) {
+        let file = std::fs::File::create(base_path.join("metadata.json")).expect("create failed");
+        serde_json::to_writer_pretty(file, &self).expect("Serialization failed");
+    }
+
+    /// Seconds since the unix epoch, what the timestamps are stored as
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+/// Size of all files in a folder in bytes
+pub fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
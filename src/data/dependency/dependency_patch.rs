@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use owo_colors::OwoColorize;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::package::PackageConfig,
+    utils::{git, progress, sync},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyPatch {
+    /// Id of the dependency the patches are for
+    pub id: String,
+    /// Only patch versions in this range, every version if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_range: Option<VersionReq>,
+    /// Patch files relative to the package, applied in order to the restored folder of the dependency
+    pub files: Vec<PathBuf>,
+}
+
+impl DependencyPatch {
+    pub fn applies_to(&self, id: &str, version: &Version) -> bool {
+        self.id == id
+            && self
+                .version_range
+                .as_ref()
+                .map_or(true, |range| range.matches(version))
+    }
+
+    /// The patch files in qpm.json for this dependency, in the order they are applied
+    fn files_for(id: &str, version: &Version) -> Vec<PathBuf> {
+        PackageConfig::read()
+            .patches
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|patch| patch.applies_to(id, version))
+            .flat_map(|patch| patch.files)
+            .collect()
+    }
+
+    pub fn has_patches(id: &str, version: &Version) -> bool {
+        !DependencyPatch::files_for(id, version).is_empty()
+    }
+
+    /// Applies the patches in qpm.json that are for this dependency to where it was restored
+    ///
+    /// Files the patches touch are copied out of the cache first if they were symlinked, so the cache is never changed
+    pub fn apply_all(id: &str, version: &Version, restored_path: &Path) {
+        let patches = DependencyPatch::files_for(id, version);
+        if patches.is_empty() {
+            return;
+        }
+
+        if !git::is_installed() {
+            panic!(
+                "{} {} has patches, which are applied with git, install git ({}) to restore it",
+                id.bright_red(),
+                version.bright_green(),
+                "https://git-scm.com/downloads".bright_yellow()
+            );
+        }
+
+        for patch in patches.iter() {
+            let patch_path = patch.canonicalize().unwrap_or_else(|e| {
+                panic!(
+                    "Patch {} for {} could not be found: {}",
+                    patch.display().bright_yellow(),
+                    id.bright_red(),
+                    e
+                )
+            });
+
+            // git won't patch through symlinks, splitting them keeps what the files contain the same
+            for path in git::patched_paths(restored_path, &patch_path) {
+                sync::split_symlinks(restored_path, &path);
+            }
+
+            // checked first, so a patch that doesn't apply leaves nothing half patched behind
+            if let Err(e) = git::apply_patch(restored_path, &patch_path, true) {
+                panic!(
+                    "Patch {} does not apply to {} {}, it probably needs to be updated for this version:\n{}",
+                    patch.display().bright_yellow(),
+                    id.bright_red(),
+                    version.bright_green(),
+                    e
+                );
+            }
+
+            if let Err(e) = git::apply_patch(restored_path, &patch_path, false) {
+                panic!(
+                    "Applying patch {} to {} {} failed:\n{}",
+                    patch.display().bright_yellow(),
+                    id.bright_red(),
+                    version.bright_green(),
+                    e
+                );
+            }
+
+            progress::println(format!(
+                "Applied patch {} to {} {}",
+                patch.display().bright_yellow(),
+                id.bright_red(),
+                version.bright_green()
+            ));
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::path::{Path, PathBuf};
+
+use owo_colors::OwoColorize;
+use remove_dir_all::remove_dir_all;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    data::{config::Config, package::PackageConfig},
+    utils::{progress, sync},
+};
+
+/// The rules that are currently applied to a cache entry
+const APPLIED_FILE: &str = "rewrites.json";
+/// Untouched copies of every file a rule changed, so rules can be undone
+const ORIGINALS_DIR: &str = "rewrite-originals";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IncludeRewrite {
+    /// Text to look for in the shared files of a package, like #include "extern/beatsaber-hook/
+    pub pattern: String,
+    /// What the pattern is replaced with, like #include "beatsaber-hook/
+    pub replacement: String,
+    /// Only rewrite the package with this id, every package if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Only rewrite versions in this range, every version if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_range: Option<VersionReq>,
+}
+
+impl IncludeRewrite {
+    pub fn applies_to(&self, id: &str, version: &Version) -> bool {
+        self.id.as_ref().map_or(true, |rule_id| rule_id == id)
+            && self
+                .version_range
+                .as_ref()
+                .map_or(true, |range| range.matches(version))
+    }
+
+    /// The rules from the global config, the only ones that change the cache since every project shares it
+    pub fn for_cache() -> Vec<IncludeRewrite> {
+        Config::read().include_rewrites.unwrap_or_default()
+    }
+
+    /// The rules from the local config, followed by those in the qpm.json of the current package, applied when restoring
+    pub fn for_project() -> Vec<IncludeRewrite> {
+        let mut rules = Config::read_local().include_rewrites.unwrap_or_default();
+        if PackageConfig::check() {
+            rules.extend(PackageConfig::read().include_rewrites.unwrap_or_default());
+        }
+        rules
+    }
+
+    /// Applies the rules to the shared files of a dependency that is being restored, without changing the cache
+    ///
+    /// Symlinks to the cache are split for every file that changes, returns how many files were rewritten
+    pub fn apply_to_restored(
+        restored_path: &Path,
+        shared_dir: &Path,
+        id: &str,
+        version: &Version,
+        rules: &[IncludeRewrite],
+    ) -> usize {
+        let wanted: Vec<&IncludeRewrite> = rules
+            .iter()
+            .filter(|rule| rule.applies_to(id, version))
+            .collect();
+        if wanted.is_empty() {
+            return 0;
+        }
+
+        // collected first, splitting symlinks while walking through them would change what is walked
+        let rewritten: Vec<(PathBuf, String)> = WalkDir::new(restored_path.join(shared_dir))
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| {
+                let original = std::fs::read_to_string(entry.path()).ok()?;
+                let content = wanted.iter().fold(original.clone(), |content, rule| {
+                    content.replace(&rule.pattern, &rule.replacement)
+                });
+                if content == original {
+                    return None;
+                }
+                Some((
+                    entry
+                        .path()
+                        .strip_prefix(restored_path)
+                        .unwrap()
+                        .to_path_buf(),
+                    content,
+                ))
+            })
+            .collect();
+
+        for (relative, content) in rewritten.iter() {
+            sync::split_symlinks(restored_path, relative);
+            let path = restored_path.join(relative);
+            std::fs::write(&path, content).unwrap_or_else(|e| {
+                panic!(
+                    "Rewriting includes in {} failed: {}",
+                    path.display().bright_yellow(),
+                    e
+                )
+            });
+        }
+        rewritten.len()
+    }
+
+    /// Makes the shared files of a cache entry have exactly the rules that apply to it applied, undoing earlier ones that no longer apply
+    pub fn sync_entry(base_path: &Path, id: &str, version: &Version, rules: &[IncludeRewrite]) {
+        let wanted: Vec<IncludeRewrite> = rules
+            .iter()
+            .filter(|rule| rule.applies_to(id, version))
+            .cloned()
+            .collect();
+        let applied_path = base_path.join(APPLIED_FILE);
+        let applied: Vec<IncludeRewrite> = std::fs::File::open(&applied_path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default();
+
+        if wanted == applied {
+            return;
+        }
+
+        restore_originals(base_path);
+        if wanted.is_empty() {
+            std::fs::remove_file(&applied_path).ok();
+            progress::println(format!(
+                "Undid include rewrites of {} {}",
+                id.bright_red(),
+                version.bright_green()
+            ));
+            return;
+        }
+
+        let src_path = base_path.join("src");
+        let shared_path =
+            src_path.join(PackageConfig::read_path(src_path.join("qpm.json")).shared_dir);
+        let mut rewritten = 0;
+        for entry in WalkDir::new(&shared_path)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+        {
+            let path = entry.path();
+            // binary files are never includes
+            let original = match std::fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+
+            let content = wanted.iter().fold(original.clone(), |content, rule| {
+                content.replace(&rule.pattern, &rule.replacement)
+            });
+            if content == original {
+                continue;
+            }
+
+            let backup_path = base_path
+                .join(ORIGINALS_DIR)
+                .join(path.strip_prefix(&src_path).unwrap());
+            std::fs::create_dir_all(backup_path.parent().unwrap())
+                .expect("Failed to create rewrite originals folder");
+            std::fs::write(&backup_path, &original).unwrap_or_else(|e| {
+                panic!(
+                    "Keeping the original of {} failed: {}",
+                    path.display().bright_yellow(),
+                    e
+                )
+            });
+            std::fs::write(path, content).unwrap_or_else(|e| {
+                panic!(
+                    "Rewriting includes in {} failed: {}",
+                    path.display().bright_yellow(),
+                    e
+                )
+            });
+            rewritten += 1;
+        }
+
+        let file = std::fs::File::create(&applied_path).expect("create failed");
+        serde_json::to_writer_pretty(file, &wanted).expect("Serialization failed");
+        progress::println(format!(
+            "Rewrote includes in {} files of {} {}",
+            rewritten.bright_yellow(),
+            id.bright_red(),
+            version.bright_green()
+        ));
+    }
+
+    /// Drops what was recorded about rewrites, for when the sources are downloaded again
+    pub fn forget_entry(base_path: &Path) {
+        std::fs::remove_file(base_path.join(APPLIED_FILE)).ok();
+        let originals_path = base_path.join(ORIGINALS_DIR);
+        if originals_path.exists() {
+            remove_dir_all(&originals_path).expect("Failed to remove rewrite originals folder");
+        }
+    }
+}
+
+/// Puts back every file a rule changed
+fn restore_originals(base_path: &Path) {
+    let originals_path = base_path.join(ORIGINALS_DIR);
+    if !originals_path.exists() {
+        return;
+    }
+
+    for entry in WalkDir::new(&originals_path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let target = base_path
+            .join("src")
+            .join(entry.path().strip_prefix(&originals_path).unwrap());
+        std::fs::copy(entry.path(), &target).unwrap_or_else(|e| {
+            panic!(
+                "Restoring the original of {} failed: {}",
+                target.display().bright_yellow(),
+                e
+            )
+        });
+    }
+
+    remove_dir_all(&originals_path).expect("Failed to remove rewrite originals folder");
+}
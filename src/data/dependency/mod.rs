@@ -11,3 +11,20 @@ mod shared_dependency;
 ///
 /// It is also used to check out how it was configured when you generated the library
 pub type SharedDependency = shared_dependency::SharedDependency;
+
+mod artifact_checksums;
+/// Sha256 checksums of the artifacts that were downloaded for a dependency
+pub type ArtifactChecksums = artifact_checksums::ArtifactChecksums;
+
+mod cache_metadata;
+/// What was downloaded into a cache entry, and from where
+pub type CacheMetadata = cache_metadata::CacheMetadata;
+pub use cache_metadata::dir_size;
+
+mod include_rewrite;
+/// A text replacement applied to the shared files of cached packages, like moving includes to a new layout
+pub type IncludeRewrite = include_rewrite::IncludeRewrite;
+
+mod dependency_patch;
+/// Patch files that are applied to a dependency after it was restored, to carry fixes until upstream has them
+pub type DependencyPatch = dependency_patch::DependencyPatch;
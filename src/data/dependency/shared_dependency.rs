@@ -1,35 +1,48 @@
-use std::{
-    io::{Cursor, Read, Write},
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
-use fs_extra::{dir::copy as copy_directory, file::copy as copy_file};
 use owo_colors::OwoColorize;
 use remove_dir_all::remove_dir_all;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
-use zip::ZipArchive;
 
-use super::Dependency;
+use super::{
+    dir_size, ArtifactChecksums, CacheMetadata, Dependency, DependencyPatch, IncludeRewrite,
+};
 use crate::{
     data::{
         config::Config,
         package::{PackageConfig, SharedPackageConfig},
-        qpackages,
+        qpackages::{self, ResolvedPackage},
     },
-    utils::git,
+    utils::{archive, git, hash, lock, network, progress, sync},
 };
 
+/// Folder in the dependencies folder that dependencies with project rewrites or patches are put together in before they are restored
+const STAGING_DIR: &str = ".staging";
+
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct SharedDependency {
     pub dependency: Dependency,
     pub version: Version,
+    /// The registry this dependency was resolved from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// Checksums of the artifacts that were restored, verified on every later restore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<ArtifactChecksums>,
+    /// The commit the sources were cloned at, later restores check out the same one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
 }
 
 impl SharedDependency {
     pub fn get_shared_package(&self) -> SharedPackageConfig {
-        qpackages::get_shared_package(&self.dependency.id, &self.version)
+        qpackages::get_shared_package_from(
+            self.registry.as_deref(),
+            &self.dependency.id,
+            &self.version,
+        )
     }
 
     pub fn get_so_name(&self) -> String {
@@ -54,80 +67,262 @@ impl SharedDependency {
             ))
     }
 
-    pub fn cache(&self) {
+    /// The folder this dependency is cached in, or the vendored copy if the package was vendored
+    pub fn cache_path(&self) -> PathBuf {
+        let base_path = if let Some(vendor_dir) = qpackages::get_vendor_dir() {
+            vendor_dir
+        } else {
+            Config::read_combine().cache.unwrap()
+        };
+
+        base_path
+            .join(&self.dependency.id)
+            .join(self.version.to_string())
+    }
+
+    pub fn cache(&mut self) {
         // Check if already cached
         // if true, don't download repo / header files
         // else cache to tmp folder in package id folder @ cache path
-        //          git repo -> git clone w/ or without github token
-        //          not git repo (no github.com) -> assume it's a zip
+        //          git repo -> git clone w/ or without the token for its host
+        //          not git repo -> assume it's an archive
         //          !! HANDLE SUBFOLDER FROM TMP, OR IF NO SUBFOLDER JUST RENAME TMP TO SRC !!
         //          -- now we have the header files --
         // Check if .so files are downloaded, if not:
         // Download release .so and possibly debug .so to libs folder, if from github use token if available
         // Now it should be cached!
 
-        println!(
+        progress::println(format!(
             "Checking cache for dependency {} {}",
             self.dependency.id.bright_red(),
             self.version.bright_green()
-        );
-        let config = Config::read_combine();
-        let base_path = config
-            .cache
-            .unwrap()
-            .join(&self.dependency.id)
-            .join(self.version.to_string());
+        ));
+        let base_path = self.cache_path();
 
         let src_path = base_path.join("src");
         let lib_path = base_path.join("lib");
-        let tmp_path = base_path.join("tmp");
+        // unique per process, so a crashed or concurrent restore never shares a staging folder
+        let tmp_path = base_path.join(format!("tmp-{}", std::process::id()));
 
         let shared_package = self.get_shared_package();
 
-        let so_path = lib_path.join(shared_package.config.get_so_name());
-        let debug_so_path = lib_path.join(format!("debug_{}", shared_package.config.get_so_name()));
+        if qpackages::get_vendor_dir().is_some() {
+            // vendored dependencies are never downloaded, they should have been copied over by qpm vendor
+            if !src_path.exists() {
+                panic!(
+                    "Vendored dependency {} {} is missing its sources, run {} to vendor it again",
+                    self.dependency.id.bright_red(),
+                    self.version.bright_green(),
+                    "qpm vendor".bright_yellow()
+                );
+            }
+            self.check_checksums(&base_path, &shared_package);
+            return;
+        }
+
+        // only one process populates an entry at a time, released when this function returns
+        let _lock = lock::lock_exclusive(&lock::entry_lock_path(&base_path));
+        // gc could have removed the entry while this process was waiting for the lock
+        std::fs::create_dir_all(&base_path).expect("Failed to create cache folder");
+
+        let additional_data = &shared_package.config.info.additional_data;
+        let existing_metadata = CacheMetadata::read_path(&base_path);
+        let mut metadata = existing_metadata
+            .clone()
+            .unwrap_or_else(|| CacheMetadata::new(&self.dependency.id, &self.version));
+        metadata.source_url = shared_package.config.info.url.clone();
+        metadata.branch = additional_data.branch_name.clone();
+        metadata.so_link = additional_data.so_link.clone();
+        metadata.debug_so_link = additional_data.debug_so_link.clone();
+        // downloads are checked against these before they are moved into the entry
+        let published = ArtifactChecksums::published(additional_data);
+
+        let so_name = shared_package.config.get_so_name();
+        let so_path = lib_path.join(&so_name);
+        let debug_so_path = lib_path.join(format!("debug_{}", so_name));
+
+        // a src folder without a qpm.json is left over from a broken download, fetch it again
+        if src_path.exists() && !src_path.join("qpm.json").exists() {
+            progress::println(format!(
+                "Cached sources of {} {} are incomplete, downloading them again",
+                self.dependency.id.bright_red(),
+                self.version.bright_green()
+            ));
+            remove_dir_all(&src_path).expect("Failed to remove broken src folder");
+            std::fs::remove_file(base_path.join("checksums.json")).ok();
+        }
+
+        // a commit recorded in qpm.shared.json wins, so a branch or tag that moved still restores the same sources
+        let pinned_commit = self
+            .commit
+            .clone()
+            .or_else(|| additional_data.commit.clone());
+        if let Some(pinned) = pinned_commit.as_ref().filter(|_| src_path.exists()) {
+            // sources cached without a recorded commit only count if they are a repo that can still tell
+            let cached = metadata.commit.clone().or_else(|| {
+                if src_path.join(".git").exists() {
+                    git::resolve_commit(&src_path)
+                } else {
+                    None
+                }
+            });
+            if !cached
+                .as_ref()
+                .map_or(false, |cached| git::same_commit(pinned, cached))
+            {
+                let cached = match &cached {
+                    Some(cached) => format!("at commit {}", cached),
+                    None => "at an unknown commit".to_string(),
+                };
+                progress::println(format!(
+                    "Cached sources of {} {} are {}, but {} is pinned, downloading them again",
+                    self.dependency.id.bright_red(),
+                    self.version.bright_green(),
+                    cached,
+                    pinned
+                ));
+                remove_dir_all(&src_path).expect("Failed to remove outdated src folder");
+                std::fs::remove_file(base_path.join("checksums.json")).ok();
+            }
+        }
 
         // Downloads the repo / zip file into src folder w/ subfolder taken into account
         if !src_path.exists() {
-            // if the tmp path exists, but src doesn't, that's a failed cache, delete it and try again!
-            if tmp_path.exists() {
-                remove_dir_all(&tmp_path).expect("Failed to remove existing tmp folder");
+            // rewrites recorded for the old sources mean nothing for new ones
+            IncludeRewrite::forget_entry(&base_path);
+
+            // if a tmp path exists, but src doesn't, that's a failed cache, delete it and try again!
+            // we hold the lock, so no other process is still writing into it
+            for entry in std::fs::read_dir(&base_path).unwrap().flatten() {
+                if entry.file_name().to_string_lossy().starts_with("tmp") && entry.path().is_dir() {
+                    remove_dir_all(entry.path()).expect("Failed to remove existing tmp folder");
+                }
             }
 
             // src did not exist, this means that we need to download the repo/zip file from packageconfig.info.url
             std::fs::create_dir_all(&src_path.parent().unwrap())
                 .expect("Failed to create lib path");
             let url = shared_package.config.info.url.unwrap();
-            if url.contains("github.com") {
-                // github url!
-                git::clone(
-                    url,
-                    shared_package.config.info.additional_data.branch_name,
-                    &tmp_path,
-                );
+            let source_root = if git::is_repo_url(&url) {
+                let reference = additional_data
+                    .tag
+                    .clone()
+                    .or_else(|| additional_data.branch_name.clone());
+                // the package decides what it needs, anything it leaves open comes from the config
+                let submodules = additional_data
+                    .submodules
+                    .clone()
+                    .unwrap_or_default()
+                    .or(&Config::read_combine().submodules.unwrap_or_default());
+                if git::use_git() {
+                    // git repo!
+                    if let Some(sub_folder) = &additional_data.sub_folder {
+                        // only the package's folder is checked out, monorepos are not cloned in full for every package
+                        git::checkout_sparse(
+                            &url,
+                            reference,
+                            pinned_commit.as_deref(),
+                            sub_folder,
+                            &submodules,
+                            &tmp_path,
+                        );
+                    } else {
+                        git::clone(
+                            url,
+                            reference,
+                            pinned_commit.as_deref(),
+                            &submodules,
+                            &tmp_path,
+                        );
+                    }
+                    metadata.commit = git::resolve_commit(&tmp_path);
+                    tmp_path.clone()
+                } else {
+                    // without git the host's archive of the same commit, tag or branch has the same files
+                    let archive_path = base_path.join("src.archive");
+                    let (archive_url, commit) = git::download_archive(
+                        &url,
+                        pinned_commit.as_deref().or(reference.as_deref()),
+                        &archive_path,
+                    );
+                    // the host knows the full commit of a branch or tag, so later restores get the same sources
+                    metadata.commit = commit.or_else(|| pinned_commit.clone());
+                    if metadata.commit.is_none() {
+                        // without a commit the archive's hash is the only way to notice different sources later
+                        ArtifactChecksums {
+                            source: Some(self.verify_download(
+                                "source archive",
+                                &archive_path,
+                                &published,
+                                |checksums| &checksums.source,
+                            )),
+                            ..Default::default()
+                        }
+                        .write_path(&base_path.join("checksums.json"));
+                    }
+                    let root = archive::extract(&archive_path, &archive_url, &tmp_path);
+                    std::fs::remove_file(&archive_path)
+                        .expect("Failed to remove downloaded archive");
+                    if root.join(".gitmodules").exists() {
+                        progress::println(format!(
+                            "{} {} uses submodules, which are not in the archive, clone it with git to get them",
+                            self.dependency.id.bright_red(),
+                            self.version.bright_green()
+                        ));
+                    }
+                    root
+                }
             } else {
-                // not a github url, assume it's a zip
-                let mut buffer = Cursor::new(Vec::new());
-                reqwest::blocking::get(&url)
-                    .unwrap()
-                    .read_to_end(buffer.get_mut())
-                    .unwrap();
+                // not a repo, so it's an archive, which could also be a github archive or release asset
+                // downloaded next to the entry so an interrupted download can be resumed next time
+                let archive_path = base_path.join("src.archive");
+                network::download_file(&url, &archive_path);
+                metadata.commit = None;
+                // the archive itself is removed after extracting, so remember its hash in the entry
+                ArtifactChecksums {
+                    source: Some(self.verify_download(
+                        "source archive",
+                        &archive_path,
+                        &published,
+                        |checksums| &checksums.source,
+                    )),
+                    ..Default::default()
+                }
+                .write_path(&base_path.join("checksums.json"));
                 // Extract to tmp folder
-                ZipArchive::new(buffer).unwrap().extract(&tmp_path).unwrap();
-            }
-            // the only way the above if else would break is if someone put a link to a zip file on github in the url slot
-            // if you are reading this and think of doing that so I have to fix this, fuck you
+                let root = archive::extract(&archive_path, &url, &tmp_path);
+                std::fs::remove_file(&archive_path).expect("Failed to remove downloaded archive");
+                root
+            };
 
             let from_path =
                 if let Some(sub_folder) = shared_package.config.info.additional_data.sub_folder {
                     // the package exists in a subfolder of the downloaded thing, just move the subfolder to src
-                    tmp_path.join(sub_folder)
+                    // archives with a single top level folder (like github archives) don't include it in the sub folder
+                    if tmp_path.join(&sub_folder).exists() {
+                        tmp_path.join(sub_folder)
+                    } else {
+                        source_root.join(sub_folder)
+                    }
                 } else {
                     // the downloaded thing IS the package, just rename the folder to src
-                    tmp_path.clone()
+                    source_root
                 };
 
             if from_path.exists() {
+                // check the version before src exists, so the cache never holds the wrong package
+                let downloaded_package = PackageConfig::read_path(from_path.join("qpm.json"));
+
+                // check if downloaded config is the same version as expected, if not, panic
+                if downloaded_package.info.version != self.version {
+                    panic!(
+                        "Downloaded package ({}) version ({}) does not match expected version ({})!",
+                        self.dependency.id.bright_red(),
+                        downloaded_package.info.version.to_string().bright_green(),
+                        self.version.to_string().bright_green(),
+                    )
+                }
+
                 // only log this on debug builds
                 #[cfg(debug_assertions)]
                 println!(
@@ -136,8 +331,9 @@ impl SharedDependency {
                     src_path.display().bright_yellow()
                 );
 
-                // HACK: renaming seems to work, idk if it works for actual subfolders?
+                // renaming is atomic, other processes either see no src or a complete one
                 std::fs::rename(&from_path, &src_path).expect("Failed to move folder");
+                metadata.source_downloaded = Some(CacheMetadata::now());
             } else {
                 panic!("Failed to restore folder for this dependency\nif you have a token configured check if it's still valid\nIf it is, check if you can manually reach the repo");
             }
@@ -146,90 +342,255 @@ impl SharedDependency {
             if tmp_path.exists() {
                 std::fs::remove_dir_all(tmp_path).expect("Failed to remove tmp folder");
             }
-            let package_path = src_path.join("qpm.json");
-            let downloaded_package = PackageConfig::read_path(package_path);
+        }
 
-            // check if downloaded config is the same version as expected, if not, panic
-            if downloaded_package.info.version != self.version {
-                panic!(
-                    "Downloaded package ({}) version ({}) does not match expected version ({})!",
-                    self.dependency.id.bright_red(),
-                    downloaded_package.info.version.to_string().bright_green(),
-                    self.version.to_string().bright_green(),
-                )
+        std::fs::create_dir_all(&lib_path).expect("Failed to create lib path");
+        // every lib is checked on its own, so one that failed to download is fetched again
+        // we need to download from packageconfig.info.additional_data.so_link and packageconfig.info.additional_data.debug_so_link
+        if !is_present(&so_path) {
+            if let Some(so_link) = shared_package.config.info.additional_data.so_link {
+                // so_link existed, download, release assets on git hosts use the token for that host
+                self.download_lib(&so_link, &so_path, "so", &published, |checksums| {
+                    &checksums.so
+                });
+                metadata.so_downloaded = Some(CacheMetadata::now());
             }
         }
 
-        if !lib_path.exists() {
-            std::fs::create_dir_all(&lib_path).expect("Failed to create lib path");
-            // libs didn't exist or the release object didn't exist, we need to download from packageconfig.info.additional_data.so_link and packageconfig.info.additional_data.debug_so_link
-            if !so_path.exists() {
-                if let Some(so_link) = shared_package.config.info.additional_data.so_link {
-                    // so_link existed, download
-                    if so_link.contains("github.com") {
-                        // github url!
-                        git::get_release(so_link, &so_path);
-                    } else {
-                        // other dl link, assume it's a raw lib file download
-                        let mut buffer = Cursor::new(Vec::new());
-                        reqwest::blocking::get(&so_link)
-                            .unwrap()
-                            .read_to_end(buffer.get_mut())
-                            .unwrap();
-                        let mut file =
-                            std::fs::File::create(so_path).expect("create so file failed");
-                        file.write_all(&*buffer.into_inner())
-                            .expect("Failed to write out downloaded bytes");
-                    }
-                }
+        if !is_present(&debug_so_path) {
+            if let Some(debug_so_link) = shared_package.config.info.additional_data.debug_so_link {
+                // debug_so_link existed, download, release assets on git hosts use the token for that host
+                self.download_lib(
+                    &debug_so_link,
+                    &debug_so_path,
+                    "debug so",
+                    &published,
+                    |checksums| &checksums.debug_so,
+                );
+                metadata.debug_so_downloaded = Some(CacheMetadata::now());
             }
+        }
 
-            if !debug_so_path.exists() {
-                if let Some(debug_so_link) =
-                    shared_package.config.info.additional_data.debug_so_link
-                {
-                    // debug_so_link existed, download
-                    if debug_so_link.contains("github.com") {
-                        // github url!
-                        git::get_release(debug_so_link, &debug_so_path);
-                    } else {
-                        // other dl link, assume it's a raw lib file download
-                        let mut buffer = Cursor::new(Vec::new());
-                        reqwest::blocking::get(&debug_so_link)
-                            .unwrap()
-                            .read_to_end(buffer.get_mut())
-                            .unwrap();
-                        let mut file =
-                            std::fs::File::create(debug_so_path).expect("create so file failed");
-                        file.write_all(&*buffer.into_inner())
-                            .expect("Failed to write out downloaded bytes");
-                    }
+        IncludeRewrite::sync_entry(
+            &base_path,
+            &self.dependency.id,
+            &self.version,
+            &IncludeRewrite::for_cache(),
+        );
+
+        // only rewritten when something changed, measuring the size walks the whole entry
+        if existing_metadata.as_ref() != Some(&metadata) {
+            metadata.size = Some(dir_size(&base_path));
+            metadata.write_path(&base_path);
+        }
+
+        if metadata.commit.is_some() {
+            self.commit = metadata.commit;
+        }
+        self.check_checksums(&base_path, &self.get_shared_package());
+    }
+
+    /// Verifies the cache entry against the published and recorded checksums, and records the ones that were not known yet
+    fn check_checksums(&mut self, base_path: &Path, shared_package: &SharedPackageConfig) {
+        let actual =
+            ArtifactChecksums::of_cache_entry(base_path, &shared_package.config.get_so_name());
+
+        ArtifactChecksums::published(&shared_package.config.info.additional_data).verify(
+            &actual,
+            "the published package",
+            &self.dependency.id,
+            &self.version,
+        );
+
+        if let Some(recorded) = &self.checksums {
+            recorded.verify(
+                &actual,
+                "qpm.shared.json",
+                &self.dependency.id,
+                &self.version,
+            );
+        }
+
+        let mut checksums = self.checksums.take().unwrap_or_default();
+        checksums.merge_missing(&actual);
+        self.checksums = if checksums.is_empty() {
+            None
+        } else {
+            Some(checksums)
+        };
+    }
+
+    /// Downloads a lib next to the entry and only moves it into lib once it's the one that was published or recorded
+    fn download_lib(
+        &self,
+        link: &str,
+        lib_file: &Path,
+        artifact: &str,
+        published: &ArtifactChecksums,
+        checksum: fn(&ArtifactChecksums) -> &Option<String>,
+    ) {
+        // downloaded next to the entry so an interrupted download can be resumed next time
+        let download_path = self.cache_path().join(format!(
+            "{}.download",
+            lib_file.file_name().unwrap().to_string_lossy()
+        ));
+        git::get_release(link.to_string(), &download_path);
+        self.verify_download(artifact, &download_path, published, checksum);
+        std::fs::rename(&download_path, lib_file).expect("Failed to move downloaded lib");
+    }
+
+    /// Panics if a download is not the artifact that was published or recorded, it's removed first so it's never cached
+    ///
+    /// `checksum` picks the artifact's checksum, returns the checksum of the download
+    fn verify_download(
+        &self,
+        artifact: &str,
+        path: &Path,
+        published: &ArtifactChecksums,
+        checksum: fn(&ArtifactChecksums) -> &Option<String>,
+    ) -> String {
+        let got = hash::sha256_file(path);
+        let recorded = self
+            .checksums
+            .as_ref()
+            .and_then(|recorded| checksum(recorded).as_ref());
+
+        for (expected, expected_from) in [
+            (checksum(published).as_ref(), "the published package"),
+            (recorded, "qpm.shared.json"),
+        ] {
+            if let Some(expected) = expected {
+                if *expected != got {
+                    std::fs::remove_file(path).ok();
                 }
+                ArtifactChecksums::verify_artifact(
+                    artifact,
+                    expected,
+                    &got,
+                    expected_from,
+                    &self.dependency.id,
+                    &self.version,
+                );
             }
         }
+        got
     }
 
-    pub fn restore_from_cache(&self, also_lib: bool) {
-        // restore from cached files, give error on fail (nonexistent?)
-        if Config::read_combine().symlink.unwrap_or(false) {
-            self.restore_from_cache_symlink(also_lib);
+    /// Restores the dependency into the dependencies folder, only touching files that differ from what is there
+    ///
+    /// Returns the paths it was restored to, and whether anything changed
+    pub fn restore_from_cache(&self, also_lib: bool) -> (Vec<PathBuf>, bool) {
+        // keeps gc and repair from removing the entry while it's being copied
+        let _lock = if qpackages::get_vendor_dir().is_none() {
+            Some(lock::lock_shared(&lock::entry_lock_path(
+                &self.cache_path(),
+            )))
+        } else {
+            None
+        };
+        self.mark_used();
+
+        // the cache could have been changed since it was checked, never link something unexpected
+        if let Some(recorded) = &self.checksums {
+            let actual = ArtifactChecksums::of_cache_entry(
+                &self.cache_path(),
+                &self.get_shared_package().config.get_so_name(),
+            );
+            recorded.verify(
+                &actual,
+                "qpm.shared.json",
+                &self.dependency.id,
+                &self.version,
+            );
+        }
+
+        let to_copy = self.collect_to_copy(also_lib);
+        let link = Config::read_combine().symlink.unwrap_or(false);
+        let dependencies_path = PackageConfig::read()
+            .dependencies_dir
+            .canonicalize()
+            .unwrap();
+        let local_path = dependencies_path.join("includes").join(&self.dependency.id);
+
+        let project_rules = IncludeRewrite::for_project();
+        let has_project_rules = project_rules
+            .iter()
+            .any(|rule| rule.applies_to(&self.dependency.id, &self.version));
+
+        let changed = if has_project_rules
+            || DependencyPatch::has_patches(&self.dependency.id, &self.version)
+        {
+            // put together in a staging folder first, so files only change when the rewritten and patched result does
+            let staging_path = dependencies_path
+                .join(STAGING_DIR)
+                .join(&self.dependency.id);
+            sync::remove_path(&staging_path);
+
+            let mut changed = false;
+            for (from, to) in to_copy.iter() {
+                match to.strip_prefix(&local_path) {
+                    Ok(relative) => {
+                        self.restore_path(from, &staging_path.join(relative), link);
+                    }
+                    Err(_) => changed |= self.restore_path(from, to, link),
+                }
+            }
+            let rewritten = IncludeRewrite::apply_to_restored(
+                &staging_path,
+                &self.get_shared_package().config.shared_dir,
+                &self.dependency.id,
+                &self.version,
+                &project_rules,
+            );
+            if rewritten > 0 {
+                progress::println(format!(
+                    "Rewrote includes in {} files of {} {}",
+                    rewritten.bright_yellow(),
+                    self.dependency.id.bright_red(),
+                    self.version.bright_green()
+                ));
+            }
+            DependencyPatch::apply_all(&self.dependency.id, &self.version, &staging_path);
+            changed |= sync::copy_path(&staging_path, &local_path);
+            sync::remove_path(&dependencies_path.join(STAGING_DIR));
+            changed
         } else {
-            self.restore_from_cache_copy(also_lib);
+            to_copy.iter().fold(false, |changed, (from, to)| {
+                self.restore_path(from, to, link) || changed
+            })
+        };
+
+        (to_copy.into_iter().map(|(_, to)| to).collect(), changed)
+    }
+
+    /// Records when this cache entry was last restored, so qpm cache gc knows what is still in use
+    fn mark_used(&self) {
+        // vendored copies belong to the package, not the cache
+        if qpackages::get_vendor_dir().is_some() {
+            return;
+        }
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let marker_path = self.cache_path().join("last_used");
+        if let Err(e) = std::fs::write(&marker_path, now.to_string()) {
+            progress::println(format!(
+                "Failed to write {}: {}",
+                marker_path.display().bright_yellow(),
+                e.bright_red()
+            ));
         }
     }
 
     pub fn collect_to_copy(&self, also_lib: bool) -> Vec<(PathBuf, PathBuf)> {
         // TODO: Look into improving the way it gets all the things to copy
         // low priority since this also works
-        let config = Config::read_combine();
         let package = PackageConfig::read();
         let shared_package = self.get_shared_package();
 
-        let base_path = config
-            .cache
-            .unwrap()
-            .join(&self.dependency.id)
-            .join(self.version.to_string());
+        let base_path = self.cache_path();
         let src_path = base_path.join("src");
         let libs_path = base_path.join("lib");
         let dependencies_path = Path::new(&package.dependencies_dir);
@@ -307,110 +668,78 @@ impl SharedDependency {
         let shared_path = local_path.join(&shared_package.config.shared_dir);
         to_copy.push((cache_shared_path, shared_path));
 
-        if let Some(extra_files) = &self.dependency.additional_data.extra_files {
-            for entry in extra_files.iter() {
-                let cache_entry_path = src_path.join(entry);
-                let entry_path = local_path.join(entry);
-                to_copy.push((cache_entry_path, entry_path));
-            }
-        }
-
         let local_shared_package = SharedPackageConfig::read();
-        if let Some(dep) = local_shared_package
+        let local_extra_files = local_shared_package
             .config
             .dependencies
             .iter()
             .find(|el| el.id == self.dependency.id)
-        {
-            if let Some(extra_files) = &dep.additional_data.extra_files {
-                for entry in extra_files.iter() {
-                    let cache_entry_path = src_path.join(entry);
-                    let entry_path = local_path.join(entry);
-                    to_copy.push((cache_entry_path, entry_path));
-                }
+            .and_then(|dep| dep.additional_data.extra_files.as_ref());
+
+        let extra_files = self
+            .dependency
+            .additional_data
+            .extra_files
+            .iter()
+            .chain(local_extra_files)
+            .flatten();
+        for entry in extra_files {
+            // extra files come from the package, which should not be able to write outside of its folder
+            if !sync::is_contained(Path::new(entry)) {
+                progress::println(format!(
+                    "Extra file {} of {} is not inside the package, not restoring it",
+                    entry.bright_yellow(),
+                    self.dependency.id.bright_red()
+                ));
+                continue;
             }
+
+            let cache_entry_path = src_path.join(entry);
+            let entry_path = local_path.join(entry);
+            to_copy.push((cache_entry_path, entry_path));
         }
 
         to_copy
     }
 
-    pub fn restore_from_cache_symlink(&self, also_lib: bool) {
-        let to_copy = self.collect_to_copy(also_lib);
-        // sort out issues with the symlinking, stuff is being symlinked weirdly
-        for (from, to) in to_copy.iter() {
-            #[cfg(debug_assertions)]
-            println!(
-                "symlinking\nfrom {}\nto {}",
-                from.display().bright_yellow(),
-                to.display().bright_yellow()
-            );
+    /// Links or copies one file or folder from the cache, returns whether anything changed
+    fn restore_path(&self, from: &Path, to: &Path, link: bool) -> bool {
+        #[cfg(debug_assertions)]
+        println!(
+            "restoring\nfrom {}\nto {}",
+            from.display().bright_yellow(),
+            to.display().bright_yellow()
+        );
 
-            // make sure to parent dir exists!
-            std::fs::create_dir_all(to.parent().unwrap()).expect("Failed to create parent folder");
-            if let Err(e) = symlink::symlink_auto(&from, &to) {
-                #[cfg(windows)]
-                println!("Failed to create symlink: {}\nfalling back to copy, did the link already exist, or did you not enable windows dev mode?\nTo disable this warning (and default to copy), use the command {}", e.bright_red(), "qpm config symlink disable".bright_yellow());
-                #[cfg(not(windows))]
-                println!("Failed to create symlink: {}\nfalling back to copy, did the link already exist?\nTo disable this warning (and default to copy), use the command {}", e.bright_red(), "qpm config symlink disable".bright_yellow());
-
-                if from.is_dir() {
-                    let mut options = fs_extra::dir::CopyOptions::new();
-                    options.overwrite = true;
-                    options.copy_inside = true;
-                    options.content_only = true;
-                    options.skip_exist = true;
-                    copy_directory(&from, &to, &options).unwrap_or_else(|_| {
-                        panic!("Failed to copy directory! From {:#?} To {:#?}", &from, &to)
-                    }); // ignore warning, let it raise the error for more details.
-                } else if from.is_file() {
-                    // we can get the parent beccause this is a file path
-                    std::fs::create_dir_all(&to.parent().unwrap())
-                        .expect("Failed to create containing directory");
-                    std::fs::copy(&from, &to).expect("Failed to copy file!");
-                }
-            }
+        if !from.exists() {
+            println!("The file or folder\n\t'{}'\ndid not exist! what happened to the cache? you should probably run {} to make sure everything is in order...", from.display().bright_yellow(), "qpm cache clear".bright_yellow());
+            return false;
         }
-    }
-
-    pub fn restore_from_cache_copy(&self, also_lib: bool) {
-        // get the files to copy
-        let to_copy = self.collect_to_copy(also_lib);
-        for (from_str, to_str) in to_copy.iter() {
-            let from = Path::new(&from_str);
-            let to = Path::new(&to_str);
-
-            #[cfg(debug_assertions)]
-            println!(
-                "copying\nfrom {}\nto {}",
-                from.display().bright_yellow(),
-                to.display().bright_yellow()
-            );
 
-            // make sure to parent dir exists!
-            std::fs::create_dir_all(to.parent().unwrap()).expect("Failed to create parent folder");
-            // if dir, make sure it exists
-            if !from.exists() {
-                println!("The file or folder\n\t'{}'\ndid not exist! what happened to the cache? you should probably run {} to make sure everything is in order...", from.display().bright_yellow(), "qpm cache clear".bright_yellow());
-            } else if from.is_dir() {
-                std::fs::create_dir_all(&to).expect("Failed to create destination folder");
-                let mut options = fs_extra::dir::CopyOptions::new();
-                options.overwrite = true;
-                options.copy_inside = true;
-                options.content_only = true;
-                // copy it over
-                copy_directory(&from, &to, &options).expect("Failed to copy directory!");
-            } else if from.is_file() {
-                // if it's a file, copy that over instead
-                let mut options = fs_extra::file::CopyOptions::new();
-                options.overwrite = true;
-                copy_file(&from, &to, &options).expect("Failed to copy file!");
+        if link {
+            match sync::link_path(from, to) {
+                Ok(changed) => return changed,
+                Err(e) => {
+                    #[cfg(windows)]
+                    println!("Failed to create symlink: {}\nfalling back to copy, did you not enable windows dev mode?\nTo disable this warning (and default to copy), use the command {}", e.bright_red(), "qpm config symlink disable".bright_yellow());
+                    #[cfg(not(windows))]
+                    println!("Failed to create symlink: {}\nfalling back to copy\nTo disable this warning (and default to copy), use the command {}", e.bright_red(), "qpm config symlink disable".bright_yellow());
+                }
             }
         }
+
+        sync::copy_path(from, to)
     }
 }
 
-impl From<SharedPackageConfig> for SharedDependency {
-    fn from(shared_package: SharedPackageConfig) -> Self {
+/// Whether a downloaded file exists and is not empty
+fn is_present(path: &Path) -> bool {
+    std::fs::metadata(path).map_or(false, |m| m.len() > 0)
+}
+
+impl From<ResolvedPackage> for SharedDependency {
+    fn from(resolved: ResolvedPackage) -> Self {
+        let shared_package = resolved.package;
         let package_config = PackageConfig::read();
         let version_range = if let Some(orig) = package_config
             .dependencies
@@ -423,12 +752,15 @@ impl From<SharedPackageConfig> for SharedDependency {
         };
 
         SharedDependency {
+            registry: resolved.registry,
             dependency: Dependency {
                 id: shared_package.config.info.id.to_string(),
                 version_range,
                 additional_data: shared_package.config.info.additional_data,
             },
             version: shared_package.config.info.version,
+            checksums: None,
+            commit: None,
         }
     }
 }
@@ -6,5 +6,8 @@ pub type PackageConfig = package_config::PackageConfig;
 pub type PackageInfo = package_config::PackageInfo;
 pub type AdditionalPackageData = package_config::AdditionalPackageData;
 
+mod submodule_options;
+pub type SubmoduleOptions = submodule_options::SubmoduleOptions;
+
 mod shared_package_config;
 pub type SharedPackageConfig = shared_package_config::SharedPackageConfig;
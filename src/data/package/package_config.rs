@@ -4,8 +4,11 @@ use owo_colors::OwoColorize;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
-use super::{CompileOptions, SharedPackageConfig};
-use crate::data::dependency::{AdditionalDependencyData, Dependency};
+use super::{CompileOptions, SubmoduleOptions};
+use crate::data::{
+    dependency::{AdditionalDependencyData, Dependency, DependencyPatch, IncludeRewrite},
+    qpackages::ResolvedPackage,
+};
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct PackageConfig {
@@ -14,6 +17,15 @@ pub struct PackageConfig {
     pub info: PackageInfo,
     pub dependencies: Vec<Dependency>,
     pub additional_data: AdditionalDependencyData,
+    /// Folder that qpm vendor copied all dependencies into, restore uses it instead of the cache and registries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor_dir: Option<PathBuf>,
+    /// Replacements applied to the shared files of dependencies when they are restored, the cache is never changed by them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_rewrites: Option<Vec<IncludeRewrite>>,
+    /// Patch files applied to dependencies after they were restored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patches: Option<Vec<DependencyPatch>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
@@ -69,10 +81,22 @@ pub struct AdditionalPackageData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mod_link: Option<String>,
 
-    /// Branch name of a Github repo. Only used when a valid github url is provided
+    /// Branch name of a git repo. Only used when a git repo url is provided
     #[serde(skip_serializing_if = "Option::is_none")]
     pub branch_name: Option<String>,
 
+    /// Tag of a git repo, cloned instead of the branch. Only used when a git repo url is provided
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    /// Exact commit of a git repo, checked out instead of the tag or branch. Only used when a git repo url is provided
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+
+    /// How the submodules of the repo are fetched, falls back to the global config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submodules: Option<SubmoduleOptions>,
+
     /// Specify any additional files to be downloaded
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra_files: Option<Vec<String>>,
@@ -92,6 +116,18 @@ pub struct AdditionalPackageData {
     /// Sub folder to use from the downloaded repo / zip, so one repo can contain multiple packages
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sub_folder: Option<String>,
+
+    /// sha256 of the file behind soLink, set by qpm publish
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub so_sha256: Option<String>,
+
+    /// sha256 of the file behind debugSoLink, set by qpm publish
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_so_sha256: Option<String>,
+
+    /// sha256 of the zip behind the url, set by qpm publish when the url is not a git repo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_sha256: Option<String>,
 }
 
 impl PackageConfig {
@@ -157,7 +193,7 @@ impl PackageConfig {
         println!("Not removing dependency {} because it did not exist", id);
     }
 
-    pub fn resolve(&self) -> impl Iterator<Item = SharedPackageConfig> + '_ {
+    pub fn resolve(&self) -> impl Iterator<Item = ResolvedPackage> + '_ {
         crate::resolver::resolve(self)
     }
 
@@ -1,11 +1,21 @@
 use std::{
+    collections::VecDeque,
     io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     vec,
 };
 
+use owo_colors::OwoColorize;
 use serde::{Deserialize, Serialize};
 
-use crate::data::qpackages;
+use crate::{
+    data::qpackages,
+    utils::{progress, sync},
+};
 /// Fern: Adds line ending after each element
 /// thanks raft
 macro_rules! concatln {
@@ -37,6 +47,11 @@ impl SharedPackageConfig {
             .expect("Deserializing package failed")
     }
 
+    pub fn read_path(filepath: PathBuf) -> SharedPackageConfig {
+        let file = std::fs::File::open(filepath).expect("Opening qpm.shared.json failed");
+        serde_json::from_reader(file).expect("Deserializing package failed")
+    }
+
     pub fn write(&self) {
         let qpm_package = serde_json::to_string_pretty(&self).expect("Serialization failed");
 
@@ -68,25 +83,133 @@ impl SharedPackageConfig {
         shared_package
     }
 
-    pub fn restore(&self) {
+    /// Keeps what was recorded about dependencies that resolved to the same version last time, like their checksums and commits
+    pub fn copy_lock_data_from(&mut self, previous: &SharedPackageConfig) {
+        for shared_dep in self.restored_dependencies.iter_mut() {
+            if let Some(previous_dep) = previous.restored_dependencies.iter().find(|dep| {
+                dep.dependency.id == shared_dep.dependency.id && dep.version == shared_dep.version
+            }) {
+                if shared_dep.checksums.is_none() {
+                    shared_dep.checksums = previous_dep.checksums.clone();
+                }
+                if shared_dep.commit.is_none() {
+                    shared_dep.commit = previous_dep.commit.clone();
+                }
+            }
+        }
+    }
+
+    /// Caches every dependency with up to `jobs` downloads at the same time, then restores them in order
+    pub fn restore(&mut self, jobs: usize) {
+        self.cache_dependencies(jobs);
+
+        std::fs::create_dir_all(&self.config.dependencies_dir)
+            .expect("Failed to create dependencies folder");
+        let mut restored_paths = Vec::new();
         for to_restore in self.restored_dependencies.iter() {
             // if the shared dep is contained within the direct dependencies, link against that, always copy headers!
-            to_restore.cache();
-            to_restore.restore_from_cache(
+            let (paths, changed) = to_restore.restore_from_cache(
                 self.config
                     .dependencies
                     .iter()
                     .any(|dep| dep.id == to_restore.dependency.id),
             );
+            if changed {
+                progress::println(format!(
+                    "Restored {} {}",
+                    to_restore.dependency.id.bright_red(),
+                    to_restore.version.bright_green()
+                ));
+            }
+            restored_paths.extend(paths);
+        }
+
+        // whatever is left is from dependencies that are no longer restored, or files they no longer have
+        let dependencies_path = self.config.dependencies_dir.canonicalize().unwrap();
+        for removed in sync::prune(&dependencies_path, &restored_paths) {
+            progress::println(format!(
+                "Removed {}",
+                removed
+                    .strip_prefix(&dependencies_path)
+                    .unwrap_or(&removed)
+                    .display()
+                    .bright_yellow()
+            ));
         }
 
         self.write_extern_cmake();
         self.write_define_cmake();
     }
 
+    fn cache_dependencies(&mut self, jobs: usize) {
+        let count = self.restored_dependencies.len();
+        let queue: VecDeque<(usize, SharedDependency)> =
+            std::mem::take(&mut self.restored_dependencies)
+                .into_iter()
+                .enumerate()
+                .collect();
+        let queue = Arc::new(Mutex::new(queue));
+        let cached = Arc::new(Mutex::new(Vec::with_capacity(count)));
+        let failed = Arc::new(AtomicBool::new(false));
+        let packages_bar = progress::packages(count as u64);
+
+        let workers: Vec<_> = (0..jobs.clamp(1, count.max(1)))
+            .map(|_| {
+                let queue = queue.clone();
+                let cached = cached.clone();
+                let failed = failed.clone();
+                let packages_bar = packages_bar.clone();
+                std::thread::spawn(move || loop {
+                    // stop picking up new work once anything failed
+                    if failed.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let next = queue.lock().unwrap().pop_front();
+                    let (index, mut shared_dep) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                        shared_dep.cache()
+                    }));
+                    if let Err(e) = result {
+                        failed.store(true, Ordering::SeqCst);
+                        std::panic::resume_unwind(e);
+                    }
+
+                    let mut cached = cached.lock().unwrap();
+                    cached.push((index, shared_dep));
+                    packages_bar.inc(1);
+                    if !progress::is_interactive() {
+                        println!("Cached {}/{} packages", cached.len(), count);
+                    }
+                })
+            })
+            .collect();
+
+        let mut first_panic = None;
+        for worker in workers {
+            if let Err(e) = worker.join() {
+                first_panic.get_or_insert(e);
+            }
+        }
+        packages_bar.finish_and_clear();
+        if let Some(e) = first_panic {
+            std::panic::resume_unwind(e);
+        }
+
+        // back in resolution order, so copying into extern is the same every time
+        let mut cached = std::mem::take(&mut *cached.lock().unwrap());
+        cached.sort_by_key(|(index, _)| *index);
+        self.restored_dependencies = cached
+            .into_iter()
+            .map(|(_, shared_dep)| shared_dep)
+            .collect();
+    }
+
     pub fn write_extern_cmake(&self) {
-        let mut extern_cmake_file =
-            std::fs::File::create("extern.cmake").expect("Failed to create extern cmake file");
         let mut result = concatln!(
             "# YOU SHOULD NOT MANUALLY EDIT THIS FILE, QPM WILL VOID ALL CHANGES",
             "# always added",
@@ -269,18 +392,12 @@ impl SharedPackageConfig {
             "target_link_libraries(${COMPILE_ID} PRIVATE\n\t${so_list}\n\t${a_list}\n)\n"
         ));
 
-        extern_cmake_file
-            .write_all(result.as_bytes())
-            .expect("Failed to write out extern cmake file");
+        sync::write_if_changed(Path::new("extern.cmake"), &result);
     }
 
     pub fn write_define_cmake(&self) {
-        let mut defines_cmake_file = std::fs::File::create("qpm_defines.cmake")
-            .expect("Failed to create defines cmake file");
-
-        defines_cmake_file
-            .write_all(self.make_defines_string().as_bytes())
-            .expect("Failed to write out own define make string");
+        // rewriting them unchanged would make cmake configure again
+        sync::write_if_changed(Path::new("qpm_defines.cmake"), &self.make_defines_string());
     }
 
     pub fn make_defines_string(&self) -> String {
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// How the submodules of a package's repo are fetched when it is cloned
+/// - enabled - OPTIONAL (bool): Whether submodules are fetched at all, defaults to true.
+/// - paths - OPTIONAL (System.String[]): Only fetch the submodules at these paths.
+/// - fullHistory - OPTIONAL (bool): Fetch the full history of submodules instead of only the checked out commit.
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmoduleOptions {
+    /// Whether submodules are fetched at all, defaults to true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+
+    /// Only fetch the submodules at these paths, relative to the repo root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<String>>,
+
+    /// Fetch the full history of submodules instead of only the checked out commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full_history: Option<bool>,
+}
+
+impl SubmoduleOptions {
+    /// Fills in whatever is not set here from fallback, like the package options over the global config
+    pub fn or(&self, fallback: &SubmoduleOptions) -> SubmoduleOptions {
+        SubmoduleOptions {
+            enabled: self.enabled.or(fallback.enabled),
+            paths: self.paths.clone().or_else(|| fallback.paths.clone()),
+            full_history: self.full_history.or(fallback.full_history),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn is_full_history(&self) -> bool {
+        self.full_history.unwrap_or(false)
+    }
+}
@@ -1,30 +1,43 @@
-use std::{collections::HashMap, lazy::SyncLazy as Lazy, time::Duration};
+use std::{collections::HashMap, lazy::SyncLazy as Lazy, path::PathBuf, sync::Mutex};
 
-use atomic_refcell::AtomicRefCell;
+use owo_colors::OwoColorize;
+use reqwest::StatusCode;
 use semver::Version;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::data::{config::Config, package::SharedPackageConfig};
-static API_URL: &str = "https://qpackages.com";
+use crate::{
+    data::{
+        config::{get_token, Config, Registry},
+        package::{PackageConfig, SharedPackageConfig},
+    },
+    utils::{
+        network::{get_agent, send_with_retry, try_send_with_retry},
+        progress,
+    },
+};
 static AUTH_HEADER: &str = "not that i can come up with";
 
-static VERSIONS_CACHE: Lazy<AtomicRefCell<HashMap<String, Vec<PackageVersion>>>> =
+static VERSIONS_CACHE: Lazy<Mutex<HashMap<String, Vec<PackageVersion>>>> =
     Lazy::new(Default::default);
-static SHARED_PACKAGE_CACHE: Lazy<AtomicRefCell<HashMap<String, SharedPackageConfig>>> =
+/// What each registry answered for a package version, keyed by the url it was asked at, None if it doesn't have it
+static SHARED_PACKAGE_CACHE: Lazy<Mutex<HashMap<String, Option<SharedPackageConfig>>>> =
     Lazy::new(Default::default);
+/// Registries that could not be reached this run and why, they are not asked again
+static UNREACHABLE: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(Default::default);
 
-static AGENT: Lazy<AtomicRefCell<reqwest::blocking::Client>> = Lazy::new({
-    || {
-        AtomicRefCell::new(
-            reqwest::blocking::ClientBuilder::new()
-                .timeout(Duration::from_millis(Config::read_combine().timeout.unwrap()))
-                .user_agent(format!("questpackagemanager-rust/{}", env!("CARGO_PKG_VERSION")))
-                .build()
-                .expect("failed to build reqwest client")
-        )
-    }
+/// Folder packages are taken from instead of the registries, set by qpm vendor
+static VENDOR_DIR: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| {
+    Mutex::new(if PackageConfig::check() {
+        PackageConfig::read()
+            .vendor_dir
+            .map(|dir| std::env::current_dir().unwrap().join(dir))
+    } else {
+        None
+    })
 });
 
+static REGISTRIES: Lazy<Vec<Registry>> = Lazy::new(|| Config::read_combine().registries());
+
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, PartialEq, Eq)]
 #[allow(non_snake_case)]
 #[serde(rename_all = "camelCase")]
@@ -33,68 +46,348 @@ pub struct PackageVersion {
     pub version: Version,
 }
 
-/// Requests the appriopriate package info from qpackage.com
+/// A shared package together with the registry it was taken from
+#[derive(Clone, Debug)]
+pub struct ResolvedPackage {
+    pub package: SharedPackageConfig,
+    /// None for packages from the vendor folder or added to the cache with qpm cache add
+    pub registry: Option<String>,
+}
+
+/// All registries that should be asked about this package id, in order of priority
+fn registries_for(id: &str) -> impl Iterator<Item = &'static Registry> + '_ {
+    REGISTRIES
+        .iter()
+        .filter(move |registry| registry.handles(id))
+}
+
+/// The absolute vendor folder of the local package, if it has one
+pub fn get_vendor_dir() -> Option<PathBuf> {
+    VENDOR_DIR.lock().unwrap().clone()
+}
+
+/// Overrides the vendor folder, None makes everything go through the registries again
+pub fn set_vendor_dir(dir: Option<PathBuf>) {
+    *VENDOR_DIR.lock().unwrap() = dir;
+}
+
+/// Requests the appriopriate package info from every registry that handles this id
 pub fn get_versions(id: &str) -> Vec<PackageVersion> {
-    let url = format!("{}/{}?limit=0", API_URL, id);
+    if let Some(vendor_dir) = get_vendor_dir() {
+        return get_vendored_versions(&vendor_dir, id);
+    }
+
+    let mut versions: Vec<PackageVersion> = Vec::new();
+    let mut unreachable = None;
+    for registry in registries_for(id) {
+        let registry_versions = match get_registry_versions(registry, id) {
+            Ok(registry_versions) => registry_versions,
+            Err(e) => {
+                unreachable = Some(e);
+                continue;
+            }
+        };
 
-    if let Some(entry) = VERSIONS_CACHE.borrow().get(&url) {
-        return entry.clone();
+        for package_version in registry_versions {
+            if !versions
+                .iter()
+                .any(|v| v.version == package_version.version)
+            {
+                versions.push(package_version);
+            }
+        }
     }
 
-    let versions = AGENT
-        .borrow_mut()
-        .get(&url)
-        .send()
-        .expect("Request to qpackages.com failed")
-        .json::<Vec<PackageVersion>>()
-        .expect("Into json failed");
+    // versions added with qpm cache add are resolvable without being published, also while a registry is down
+    for package_version in get_seeded_versions(id) {
+        if !versions
+            .iter()
+            .any(|v| v.version == package_version.version)
+        {
+            versions.push(package_version);
+        }
+    }
 
-    VERSIONS_CACHE.borrow_mut().insert(url, versions.clone());
+    // the list can miss versions of a registry that is down, get_resolved_package only allows seeded ones then
+    if let Some(e) = unreachable {
+        if versions.is_empty() {
+            panic!("{}", e);
+        }
+    }
+
+    // newest first, the same as a single registry would return them
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+    versions
+}
+
+fn get_vendored_versions(vendor_dir: &std::path::Path, id: &str) -> Vec<PackageVersion> {
+    let mut versions: Vec<PackageVersion> = std::fs::read_dir(vendor_dir.join(id))
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("qpm.shared.json").exists())
+                .filter_map(|entry| entry.file_name().to_string_lossy().parse().ok())
+                .map(|version| PackageVersion {
+                    id: id.to_string(),
+                    version,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if versions.is_empty() {
+        panic!(
+            "Package {} is not vendored, run {} to vendor it",
+            id.bright_red(),
+            "qpm vendor".bright_yellow()
+        );
+    }
+
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
     versions
 }
 
+/// The folder qpm cache add puts the shared package of a version in
+fn seeded_path(id: &str, ver: &Version) -> PathBuf {
+    Config::read_combine()
+        .cache
+        .unwrap()
+        .join(id)
+        .join(ver.to_string())
+        .join("qpm.shared.json")
+}
+
+fn get_seeded_versions(id: &str) -> Vec<PackageVersion> {
+    let cache_path = Config::read_combine().cache.unwrap().join(id);
+    std::fs::read_dir(cache_path)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("qpm.shared.json").exists())
+                .filter_map(|entry| entry.file_name().to_string_lossy().parse().ok())
+                .map(|version| PackageVersion {
+                    id: id.to_string(),
+                    version,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The versions a registry has of a package, the error says why it could not be reached
+fn get_registry_versions(registry: &Registry, id: &str) -> Result<Vec<PackageVersion>, String> {
+    let url = format!("{}/{}?limit=0", registry.url, id);
+
+    if let Some(entry) = VERSIONS_CACHE.lock().unwrap().get(&url) {
+        return Ok(entry.clone());
+    }
+
+    // a registry not knowing about a package is not an error, another registry might
+    let versions =
+        get_from_registry::<Vec<PackageVersion>>(&registry.url, &url)?.unwrap_or_default();
+
+    VERSIONS_CACHE.lock().unwrap().insert(url, versions.clone());
+    Ok(versions)
+}
+
+/// Requests something from a registry, None if the registry does not have it
+///
+/// Only a 404 means that, any other status is reported instead of making the package look like it doesn't exist.
+/// A registry that can't be reached is an error the caller can fall back from, it is reported once and isn't asked again this run
+fn get_from_registry<T: DeserializeOwned>(
+    registry_url: &str,
+    url: &str,
+) -> Result<Option<T>, String> {
+    if let Some(e) = UNREACHABLE.lock().unwrap().get(registry_url) {
+        return Err(e.clone());
+    }
+
+    let response = match try_send_with_retry(url, || get_agent().get(url)) {
+        Ok(response) => response,
+        Err(e) => {
+            let e = format!(
+                "Registry {} could not be reached: {}",
+                registry_url.bright_yellow(),
+                e.without_url()
+            );
+            progress::println(format!(
+                "{}, falling back to packages added with {}",
+                e,
+                "qpm cache add".bright_yellow()
+            ));
+            UNREACHABLE
+                .lock()
+                .unwrap()
+                .insert(registry_url.to_string(), e.clone());
+            return Err(e);
+        }
+    };
+
+    Ok(match response.status() {
+        StatusCode::NOT_FOUND => None,
+        status if status.is_success() => Some(response.json::<T>().unwrap_or_else(|e| {
+            panic!(
+                "Registry response from {} is not what qpm expected: {}",
+                url.bright_yellow(),
+                e
+            )
+        })),
+        status => panic!(
+            "Request to registry {} failed with status {}",
+            url.bright_yellow(),
+            status.bright_red()
+        ),
+    })
+}
+
+/// Gets the shared package from the highest priority registry that has it
 pub fn get_shared_package(id: &str, ver: &Version) -> SharedPackageConfig {
-    let url = format!("{}/{}/{}", API_URL, id, ver);
+    get_resolved_package(None, id, ver).package
+}
+
+/// Gets the shared package, asking the preferred registry before any of the configured ones
+pub fn get_shared_package_from(
+    preferred: Option<&str>,
+    id: &str,
+    ver: &Version,
+) -> SharedPackageConfig {
+    get_resolved_package(preferred, id, ver).package
+}
+
+/// Gets the shared package and the registry it came from, asking the preferred registry before any of the configured ones
+///
+/// A registry that can't be reached is an error unless the version was added with qpm cache add, registries that the id is scoped away from are never asked
+pub fn get_resolved_package(preferred: Option<&str>, id: &str, ver: &Version) -> ResolvedPackage {
+    if let Some(vendor_dir) = get_vendor_dir() {
+        let path = vendor_dir
+            .join(id)
+            .join(ver.to_string())
+            .join("qpm.shared.json");
+        if !path.exists() {
+            panic!(
+                "Package {} version {} is not vendored, run {} to vendor it",
+                id.bright_red(),
+                ver.bright_green(),
+                "qpm vendor".bright_yellow()
+            );
+        }
 
-    if let Some(entry) = SHARED_PACKAGE_CACHE.borrow().get(&url) {
-        return entry.clone();
+        return ResolvedPackage {
+            package: SharedPackageConfig::read_path(path),
+            registry: None,
+        };
     }
 
-    let shared_package = AGENT
-        .borrow_mut()
-        .get(&url)
-        .send()
-        .expect("Request to qpackages.com failed")
-        .json::<SharedPackageConfig>()
-        .expect("Into json failed");
+    let mut urls: Vec<&str> = Vec::new();
+    if let Some(preferred) = preferred {
+        urls.push(preferred);
+    }
+    for registry in registries_for(id) {
+        if !urls.contains(&registry.url.as_str()) {
+            urls.push(&registry.url);
+        }
+    }
 
-    SHARED_PACKAGE_CACHE
-        .borrow_mut()
-        .insert(url, shared_package.clone());
-    shared_package
+    let seeded_path = seeded_path(id, ver);
+    for registry_url in urls {
+        let url = format!("{}/{}/{}", registry_url, id, ver);
+
+        let cached = SHARED_PACKAGE_CACHE.lock().unwrap().get(&url).cloned();
+        let shared_package = match cached {
+            Some(shared_package) => shared_package,
+            None => match get_from_registry::<SharedPackageConfig>(registry_url, &url) {
+                Ok(shared_package) => {
+                    SHARED_PACKAGE_CACHE
+                        .lock()
+                        .unwrap()
+                        .insert(url, shared_package.clone());
+                    shared_package
+                }
+                Err(e) => {
+                    // a registry after it could have a different package with the same id, only seeded versions are safe
+                    if seeded_path.exists() {
+                        return ResolvedPackage {
+                            package: SharedPackageConfig::read_path(seeded_path),
+                            registry: None,
+                        };
+                    }
+                    panic!("{}", e);
+                }
+            },
+        };
+
+        if let Some(package) = shared_package {
+            return ResolvedPackage {
+                package,
+                registry: Some(registry_url.to_string()),
+            };
+        }
+    }
+
+    // versions added with qpm cache add don't have to be published
+    if seeded_path.exists() {
+        return ResolvedPackage {
+            package: SharedPackageConfig::read_path(seeded_path),
+            registry: None,
+        };
+    }
+
+    panic!(
+        "Package {} version {} was not found on any configured registry or in the cache",
+        id.bright_red(),
+        ver.bright_green()
+    );
 }
 
 pub fn get_packages() -> Vec<String> {
-    AGENT
-        .borrow_mut()
-        .get(API_URL)
-        .send()
-        .expect("Request to qpackages.com failed")
-        .json::<Vec<String>>()
-        .expect("Into json failed")
+    let mut packages: Vec<String> = Vec::new();
+    for registry in REGISTRIES.iter() {
+        let ids = send_with_retry(&registry.url, || get_agent().get(&registry.url))
+            .json::<Vec<String>>()
+            .expect("Into json failed");
+
+        for id in ids {
+            if registry.handles(&id) && !packages.contains(&id) {
+                packages.push(id);
+            }
+        }
+    }
+
+    packages
 }
 
 pub fn publish_package(package: &SharedPackageConfig) {
+    let registry = registries_for(&package.config.info.id)
+        .next()
+        .expect("No registry is configured to handle this package id");
+
     let url = format!(
         "{}/{}/{}",
-        API_URL, &package.config.info.id, &package.config.info.version
+        registry.url, &package.config.info.id, &package.config.info.version
     );
 
-    AGENT
-        .borrow_mut()
+    progress::println(format!("Publishing to {}", registry.url.bright_yellow()));
+    // registries can have their own token, configured like any other host
+    let auth = get_token(&registry.url).unwrap_or_else(|| AUTH_HEADER.to_string());
+    // sent once, a publish that timed out could still have gone through
+    let response = get_agent()
         .post(&url)
-        .header("Authorization", AUTH_HEADER)
+        .header("Authorization", &auth)
         .json(package)
         .send()
-        .expect("Request to qpackages.com failed");
+        .unwrap_or_else(|e| {
+            panic!(
+                "Publishing to {} failed: {}",
+                registry.url.bright_yellow(),
+                e.without_url()
+            )
+        });
+
+    let status = response.status();
+    if !status.is_success() {
+        panic!(
+            "Publishing to {} failed with status {}: {}",
+            registry.url.bright_yellow(),
+            status.bright_red(),
+            response.text().unwrap_or_default()
+        );
+    }
 }
@@ -35,14 +35,20 @@ enum MainCommand {
     /// List all properties that are currently supported by QPM
     List(commands::list::ListOperation),
     /// Publish package
-    Publish,
+    Publish(commands::publish::Publish),
+    /// Registry control
+    Registry(commands::registry::Registry),
     /// Restore and resolve all dependencies from the package
-    Restore,
+    Restore(commands::restore::Restore),
     /// Qmod control
     Qmod(commands::qmod::Qmod),
+    /// Copy all resolved dependencies into the package, so restore works without network access
+    Vendor(commands::vendor::Vendor),
 }
 
 fn main() {
+    utils::redact::install_panic_hook();
+
     // You can handle information about subcommands by requesting their matches by name
     // (as below), requesting just the name used, or both at the same time
     match (Opts::parse() as Opts).subcmd {
@@ -53,9 +59,11 @@ fn main() {
         MainCommand::Dependency(d) => commands::dependency::execute_dependency_operation(d),
         MainCommand::Package(p) => commands::package::execute_package_operation(p),
         MainCommand::List(l) => commands::list::execute_list_operation(l),
-        MainCommand::Publish => commands::publish::execute_publish_operation(),
-        MainCommand::Restore => commands::restore::execute_restore_operation(),
+        MainCommand::Publish(p) => commands::publish::execute_publish_operation(p),
+        MainCommand::Registry(r) => commands::registry::execute_registry_operation(r),
+        MainCommand::Restore(r) => commands::restore::execute_restore_operation(r),
         MainCommand::Qmod(q) => commands::qmod::execute_qmod_operation(q),
+        MainCommand::Vendor(v) => commands::vendor::execute_vendor_operation(v),
     }
 }
 
@@ -6,28 +6,32 @@ use pubgrub::{
 };
 
 use self::provider::DependencyProvider;
-use crate::data::{
-    package::{PackageConfig, SharedPackageConfig},
-    qpackages,
+use crate::{
+    data::{
+        package::PackageConfig,
+        qpackages::{self, ResolvedPackage},
+    },
+    utils::redact,
 };
 
 mod provider;
 mod semver;
 
-pub fn resolve(root: &PackageConfig) -> impl Iterator<Item = SharedPackageConfig> + '_ {
+pub fn resolve(root: &PackageConfig) -> impl Iterator<Item = ResolvedPackage> + '_ {
     let provider = DependencyProvider::new(root);
     match pubgrub::solver::resolve(&provider, root.info.id.clone(), root.info.version.clone()) {
         Ok(deps) => deps
             .into_iter()
             .filter(move |(id, version)| !(id == &root.info.id && version == &root.info.version))
-            .map(|(id, version)| qpackages::get_shared_package(&id, &version.into())),
+            .map(|(id, version)| qpackages::get_resolved_package(None, &id, &version.into())),
         Err(PubGrubError::NoSolution(tree)) => {
             let report = DefaultStringReporter::report(&tree);
             eprintln!("failed to resolve dependencies: \n{}", report);
             process::exit(1)
         }
         Err(err) => {
-            eprintln!("{}", err);
+            // errors from fetching packages can hold registry urls with credentials
+            eprintln!("{}", redact::redact(&err.to_string()));
             process::exit(1)
         }
     }
@@ -0,0 +1,104 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use owo_colors::OwoColorize;
+use zip::ZipArchive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+    TarXz,
+    Tar,
+}
+
+impl ArchiveKind {
+    /// Detects the archive type from the magic bytes at the start of the file
+    pub fn sniff(path: &Path) -> Option<ArchiveKind> {
+        let mut header = [0u8; 262];
+        let read = std::fs::File::open(path)
+            .and_then(|mut file| file.read(&mut header))
+            .unwrap_or(0);
+        let header = &header[..read];
+
+        if header.starts_with(b"PK\x03\x04") {
+            Some(ArchiveKind::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveKind::TarGz)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(ArchiveKind::TarXz)
+        } else if header.len() >= 262 && &header[257..262] == b"ustar" {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Guesses the archive type from the url, for when the content didn't tell us
+    pub fn from_url(url: &str) -> Option<ArchiveKind> {
+        let path = url.split(&['?', '#'][..]).next().unwrap_or(url);
+        if path.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if path.ends_with(".tar.xz") || path.ends_with(".txz") {
+            Some(ArchiveKind::TarXz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extracts the archive downloaded from url into out
+///
+/// Returns the folder the contents ended up in, if everything is inside a single top level folder
+/// (like github archives, repo-branch/...) that folder is returned instead of out
+pub fn extract(archive_path: &Path, url: &str, out: &Path) -> PathBuf {
+    let kind = ArchiveKind::sniff(archive_path)
+        .or_else(|| ArchiveKind::from_url(url))
+        .unwrap_or_else(|| {
+            panic!(
+                "Downloaded source {} is not a zip, tar.gz or tar.xz archive, check the package url",
+                url.bright_yellow()
+            )
+        });
+
+    let file = std::fs::File::open(archive_path).expect("Opening archive failed");
+    match kind {
+        ArchiveKind::Zip => ZipArchive::new(file)
+            .expect("Reading zip failed")
+            .extract(out)
+            .expect("Extracting zip failed"),
+        ArchiveKind::TarGz => tar::Archive::new(flate2::read::GzDecoder::new(file))
+            .unpack(out)
+            .expect("Extracting tar.gz failed"),
+        ArchiveKind::TarXz => tar::Archive::new(xz2::read::XzDecoder::new(file))
+            .unpack(out)
+            .expect("Extracting tar.xz failed"),
+        ArchiveKind::Tar => tar::Archive::new(file)
+            .unpack(out)
+            .expect("Extracting tar failed"),
+    }
+
+    single_root(out)
+}
+
+/// The only folder in out if there is nothing else next to it, otherwise out itself
+fn single_root(out: &Path) -> PathBuf {
+    let entries: Vec<PathBuf> = std::fs::read_dir(out)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    match entries.as_slice() {
+        [only] if only.is_dir() => only.clone(),
+        _ => out.to_path_buf(),
+    }
+}
@@ -1,10 +1,63 @@
-use std::io::{Cursor, Read, Write};
+use std::{
+    collections::HashSet,
+    lazy::SyncLazy as Lazy,
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use owo_colors::OwoColorize;
 //use duct::cmd;
-use serde::{Deserialize, Serialize};
 
-use crate::data::config::get_keyring;
+use super::{git_host::HostUrl, lock, network, progress};
+use crate::data::{config::Config, package::SubmoduleOptions};
+
+/// Folder in the cache that holds the mirrors of repos that packages are checked out from
+pub const MIRRORS_DIR: &str = ".mirrors";
+
+/// Whether git is installed, only checked once
+pub fn is_installed() -> bool {
+    static INSTALLED: Lazy<bool> = Lazy::new(|| {
+        std::process::Command::new("git")
+            .arg("--version")
+            .output()
+            .map_or(false, |output| output.status.success())
+    });
+    *INSTALLED
+}
+
+/// Whether repos should be cloned, which is not the case if it's disabled in the config or git isn't installed
+pub fn use_git() -> bool {
+    static WARNED: Once = Once::new();
+    if !Config::read_combine().use_git.unwrap_or(true) {
+        return false;
+    }
+
+    if !is_installed() {
+        WARNED.call_once(|| {
+            progress::println(format!(
+                "git was not found, downloading archives of repos instead, install git ({}) to clone them",
+                "https://git-scm.com/downloads".bright_yellow()
+            ))
+        });
+        return false;
+    }
+
+    true
+}
+
+/// Downloads the archive of a repo at a branch, tag or commit instead of cloning it
+///
+/// Returns the url of the archive that was downloaded and the commit it is of, if the host could tell
+pub fn download_archive(
+    url: &str,
+    reference: Option<&str>,
+    out: &Path,
+) -> (String, Option<String>) {
+    HostUrl::parse(url)
+        .unwrap_or_else(|| panic!("{} is not an http(s) url to download", url.bright_yellow()))
+        .download_archive(reference, out)
+}
 
 pub fn check_git() {
     let mut git = std::process::Command::new("git");
@@ -35,142 +88,445 @@ pub fn check_git() {
     }
 }
 
-pub fn get_release(url: String, out: &std::path::Path) -> bool {
+/// Downloads a release asset or other file from a git host, using the token for that host if there is one
+pub fn get_release(url: String, out: &Path) -> bool {
+    match HostUrl::parse(&url) {
+        Some(host_url) => host_url.download(out),
+        None => network::download_file(&url, out),
+    }
+    out.exists()
+}
+
+/// Whether the url is a repo to clone, and not an archive or release asset that is hosted on a git host
+pub fn is_repo_url(url: &str) -> bool {
+    HostUrl::parse(url).map_or(false, |host_url| host_url.is_repo())
+}
+
+/// Clones the repo at `url`, checking out `commit` when given, or else the head of `branch` (which can also be a tag)
+pub fn clone(
+    url: String,
+    branch: Option<String>,
+    commit: Option<&str>,
+    submodules: &SubmoduleOptions,
+    out: &Path,
+) -> bool {
     check_git();
-    if let Ok(token_unwrapped) = get_keyring().get_password() {
-        get_release_with_token(url, out, &token_unwrapped)
+    let host_url = HostUrl::parse(&url)
+        .unwrap_or_else(|| panic!("{} is not an http(s) url to clone", url.bright_yellow()));
+    // the token goes into a header through the environment, so it's never in the url or the arguments
+    let auth = host_url
+        .token()
+        .map(|token| host_url.git_auth_config(&token));
+    let label = format!("Cloning {}", url);
+
+    let mut git = git_command(auth.as_ref());
+    git.arg("clone")
+        .arg(host_url.clone_url())
+        .arg(&out)
+        .arg("--quiet");
+
+    if commit.is_some() {
+        // a shallow clone can't check out an arbitrary commit, leave out the file contents of the history instead
+        git.arg("--filter=blob:none").arg("--no-checkout");
     } else {
-        get_release_without_token(url, out)
+        git.arg("--depth").arg("1").arg("--single-branch");
+
+        if submodules.is_enabled() {
+            match &submodules.paths {
+                Some(paths) => {
+                    for path in paths.iter() {
+                        git.arg(format!("--recurse-submodules={}", path));
+                    }
+                }
+                None => {
+                    git.arg("--recurse-submodules");
+                }
+            }
+
+            if submodules.is_full_history() {
+                git.arg("--no-shallow-submodules");
+            } else {
+                git.arg("--shallow-submodules");
+            }
+        }
+
+        if let Some(branch_unwrapped) = branch {
+            git.arg("-b").arg(branch_unwrapped);
+        } else {
+            progress::println("No branch name found, cloning default branch");
+        }
     }
+
+    let spinner = progress::spinner(&label);
+    let output = run_with_retries(&mut git, "Cloning", Some(out));
+    check_output(output);
+
+    if let Some(commit) = commit {
+        check_output(
+            git_command(auth.as_ref())
+                .arg("-C")
+                .arg(out)
+                .arg("checkout")
+                .arg("--quiet")
+                .arg(commit)
+                .output(),
+        );
+        update_submodules(out, submodules, auth.as_ref(), None);
+    }
+    spinner.finish_and_clear();
+
+    out.exists()
 }
 
-pub fn get_release_without_token(url: String, out: &std::path::Path) -> bool {
-    let mut buffer = Cursor::new(Vec::new());
-    reqwest::blocking::get(&url)
+/// Checks out only `sub_folder` of the repo at `url` into `out`, from a bare mirror that every package and version in that repo shares
+///
+/// The mirror is cloned without file contents, so only the files in the sub folders that were checked out get downloaded
+pub fn checkout_sparse(
+    url: &str,
+    branch: Option<String>,
+    commit: Option<&str>,
+    sub_folder: &str,
+    submodules: &SubmoduleOptions,
+    out: &Path,
+) -> bool {
+    static FETCHED: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(Default::default);
+
+    check_git();
+    let host_url = HostUrl::parse(url)
+        .unwrap_or_else(|| panic!("{} is not an http(s) url to clone", url.bright_yellow()));
+    let auth = host_url
+        .token()
+        .map(|token| host_url.git_auth_config(&token));
+
+    let mirror_path = mirrors_path().join(host_url.mirror_name());
+    // one checkout from a mirror at a time, also between processes
+    let _lock = lock::lock_exclusive(&lock::entry_lock_path(&mirror_path));
+
+    let spinner = progress::spinner(&format!("Checking out {} from {}", sub_folder, url));
+    if !mirror_path.exists() {
+        let mut git = git_command(auth.as_ref());
+        git.arg("clone")
+            .arg("--mirror")
+            .arg("--filter=blob:none")
+            .arg("--quiet")
+            .arg(host_url.clone_url())
+            .arg(&mirror_path);
+        check_output(run_with_retries(&mut git, "Cloning", Some(&mirror_path)));
+        FETCHED.lock().unwrap().insert(mirror_path.clone());
+    } else if !commit.map_or(false, |commit| has_commit(&mirror_path, commit))
+        && FETCHED.lock().unwrap().insert(mirror_path.clone())
+    {
+        // branches and tags move, so an existing mirror is updated once per run
+        let mut git = git_command(auth.as_ref());
+        git.arg("-C")
+            .arg(&mirror_path)
+            .arg("fetch")
+            .arg("--prune")
+            .arg("--quiet")
+            .arg("origin");
+        check_output(run_with_retries(&mut git, "Fetching", None));
+    }
+
+    // qpm cache gc removes mirrors that nothing was checked out from in a while
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
         .unwrap()
-        .read_to_end(buffer.get_mut())
-        .unwrap();
-    let mut file = std::fs::File::create(out).expect("create so file failed");
-    file.write_all(&*buffer.into_inner())
-        .expect("Failed to write out downloaded bytes");
+        .as_secs();
+    std::fs::write(mirror_path.join("last_used"), now.to_string()).ok();
+
+    // worktrees of earlier checkouts are gone once their sub folder was moved into the cache
+    check_output(
+        git_command(None)
+            .arg("-C")
+            .arg(&mirror_path)
+            .arg("worktree")
+            .arg("prune")
+            .output(),
+    );
+
+    let reference = commit
+        .map(|commit| commit.to_string())
+        .or(branch)
+        .unwrap_or_else(|| "HEAD".to_string());
+    check_output(
+        git_command(None)
+            .arg("-C")
+            .arg(&mirror_path)
+            .arg("worktree")
+            .arg("add")
+            .arg("--quiet")
+            .arg("--detach")
+            .arg("--no-checkout")
+            .arg(out)
+            .arg(&reference)
+            .output(),
+    );
+    check_output(
+        git_command(None)
+            .arg("-C")
+            .arg(out)
+            .arg("sparse-checkout")
+            .arg("init")
+            .arg("--cone")
+            .output(),
+    );
+    check_output(
+        git_command(None)
+            .arg("-C")
+            .arg(out)
+            .arg("sparse-checkout")
+            .arg("set")
+            .arg(sub_folder)
+            .output(),
+    );
+    // this is where the file contents of the sub folder are downloaded
+    check_output(
+        git_command(auth.as_ref())
+            .arg("-C")
+            .arg(out)
+            .arg("reset")
+            .arg("--hard")
+            .arg("--quiet")
+            .output(),
+    );
+    update_submodules(out, submodules, auth.as_ref(), Some(sub_folder));
+    spinner.finish_and_clear();
 
     out.exists()
 }
 
-pub fn get_release_with_token(url: String, out: &std::path::Path, token: &str) -> bool {
-    // had token, use it!
-    // download url for a private thing: still need to get asset id!
-    // from this: "https://github.com/$USER/$REPO/releases/download/$TAG/$FILENAME"
-    // to this: "https://$TOKEN@api.github.com/repos/$USER/$REPO/releases/assets/$ASSET_ID"
-    let split: Vec<String> = url.split('/').map(|el| el.to_string()).collect();
-
-    // Obviously this is a bad way of parsing the GH url but like I see no better way, people better not use direct lib uploads lol
-    // (I know mentioning it here will make people do that, so fuck y'all actually thinking of doing that)
-    // HACK: Not ideal way of getting these values
-    let user = split.get(3).unwrap();
-    let repo = split.get(4).unwrap();
-    let tag = split.get(7).unwrap();
-    let filename = split.get(8).unwrap();
-
-    let asset_data_link = format!(
-        "https://{}@api.github.com/repos/{}/{}/releases/tags/{}",
-        &token, &user, &repo, &tag
-    );
+/// Fetches the submodules of a repo that was checked out without them, only those in `limit_to` if given
+fn update_submodules(
+    repo: &Path,
+    submodules: &SubmoduleOptions,
+    auth: Option<&(String, String)>,
+    limit_to: Option<&str>,
+) {
+    if !submodules.is_enabled() {
+        return;
+    }
 
-    let data = match reqwest::blocking::get(&asset_data_link) {
-        Ok(o) => o.json::<GithubReleaseData>().unwrap(),
-        Err(e) => {
-            let error_string = e.to_string().replace(&token, "***");
-            panic!("{}", error_string);
-        }
-    };
+    let mut git = git_command(auth);
+    git.arg("-C")
+        .arg(repo)
+        .arg("submodule")
+        .arg("update")
+        .arg("--init")
+        .arg("--recursive")
+        .arg("--quiet");
+    if !submodules.is_full_history() {
+        git.arg("--depth").arg("1");
+    }
 
-    for asset in data.assets.iter() {
-        if asset.name.eq(filename) {
-            // this is the correct asset!
-            let mut buffer = Cursor::new(Vec::new());
-            let download = asset
-                .url
-                .replace("api.github.com", &format!("{}@api.github.com", token));
-
-            reqwest::blocking::get(&download)
-                .unwrap()
-                .read_to_end(buffer.get_mut())
-                .unwrap();
-            let mut file = std::fs::File::create(out).expect("create so file failed");
-            file.write_all(&*buffer.into_inner())
-                .expect("Failed to write out downloaded bytes");
-            break;
+    git.arg("--");
+    match &submodules.paths {
+        // an empty list means no submodules, not all of them
+        Some(paths) if paths.is_empty() => return,
+        Some(paths) => {
+            git.args(paths);
+        }
+        None => {
+            git.args(limit_to);
         }
     }
 
-    out.exists()
+    check_output(git.output());
 }
 
-pub fn clone(mut url: String, branch: Option<String>, out: &std::path::Path) -> bool {
-    check_git();
-    if let Ok(token_unwrapped) = get_keyring().get_password() {
-        if let Some(gitidx) = url.find("github.com") {
-            url.insert_str(gitidx, &format!("{}@", token_unwrapped));
+/// Where the mirrors of repos that packages are checked out from live, next to the cached packages
+pub fn mirrors_path() -> PathBuf {
+    Config::read_combine().cache.unwrap().join(MIRRORS_DIR)
+}
+
+fn has_commit(repo: &Path, commit: &str) -> bool {
+    std::process::Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("cat-file")
+        .arg("-e")
+        .arg(format!("{}^{{commit}}", commit))
+        .output()
+        .map_or(false, |output| output.status.success())
+}
+
+/// Runs the git command, trying again after a failure that looks like a dropped connection
+///
+/// `cleanup` is removed before trying again, for commands that leave a broken clone behind
+fn run_with_retries(
+    git: &mut std::process::Command,
+    action: &str,
+    cleanup: Option<&Path>,
+) -> std::io::Result<std::process::Output> {
+    let attempts = network::get_attempts();
+    let mut attempt = 1;
+    loop {
+        match git.output() {
+            Ok(o)
+                if o.status.code().unwrap_or(-1) != 0
+                    && attempt < attempts
+                    && is_transient_failure(&o.stderr) =>
+            {
+                progress::println(format!(
+                    "{} failed with exit code {}, retrying ({}/{})",
+                    action,
+                    o.status.bright_red(),
+                    attempt,
+                    attempts
+                ));
+                if let Some(cleanup) = cleanup.filter(|path| path.exists()) {
+                    remove_dir_all::remove_dir_all(cleanup).expect("Failed to remove failed clone");
+                }
+                network::backoff(attempt);
+                attempt += 1;
+            }
+            o => return o,
         }
     }
+}
+
+/// Whether git failed because of the network, bad credentials or a missing branch fail the same way when tried again
+fn is_transient_failure(stderr: &[u8]) -> bool {
+    const TRANSIENT: &[&str] = &[
+        "could not resolve host",
+        "could not resolve proxy",
+        "failed to connect to",
+        "connection timed out",
+        "operation timed out",
+        "connection reset",
+        "connection refused",
+        "the remote end hung up unexpectedly",
+        "early eof",
+        "unexpected disconnect",
+        "rpc failed",
+        "transfer closed with outstanding read data remaining",
+        "gnutls_handshake() failed",
+        "ssl_read",
+        "ssl_connect",
+        "the requested url returned error: 5",
+        "the requested url returned error: 429",
+    ];
+
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+    TRANSIENT.iter().any(|pattern| stderr.contains(pattern))
+}
 
-    if url.ends_with('/') {
-        url = url[..url.len() - 1].to_string();
+/// A git command that goes through the same proxy as our web requests, with the auth header for a host if given
+///
+/// Both are passed as environment config, which unlike -c arguments doesn't show up in the process list
+fn git_command(auth: Option<&(String, String)>) -> std::process::Command {
+    let mut config = Vec::new();
+    if let Some(proxy) = Config::read_combine().proxy {
+        config.push(("http.proxy".to_string(), proxy));
+    }
+    if let Some(auth) = auth {
+        config.push(auth.clone());
     }
 
     let mut git = std::process::Command::new("git");
-    git.arg("clone")
-        .arg(format!("{}.git", url))
-        .arg(&out)
-        .arg("--depth")
-        .arg("1")
-        .arg("--recurse-submodules")
-        .arg("--shallow-submodules")
-        .arg("--quiet")
-        .arg("--single-branch");
-
-    if let Some(branch_unwrapped) = branch {
-        git.arg("-b").arg(branch_unwrapped);
-    } else {
-        println!("No branch name found, cloning default branch");
+    if config.is_empty() {
+        return git;
     }
 
-    match git.output() {
+    git.env("GIT_CONFIG_COUNT", config.len().to_string());
+    for (index, (key, value)) in config.into_iter().enumerate() {
+        git.env(format!("GIT_CONFIG_KEY_{}", index), key)
+            .env(format!("GIT_CONFIG_VALUE_{}", index), value);
+    }
+    git
+}
+
+/// Panics with the error output of a failed git command
+fn check_output(output: std::io::Result<std::process::Output>) {
+    match output {
         Ok(_o) => {
             if _o.status.code().unwrap_or(-1) != 0 {
-                let mut error_string = std::str::from_utf8(_o.stderr.as_slice())
-                    .unwrap()
-                    .to_string();
-
-                if let Ok(token_unwrapped) = get_keyring().get_password() {
-                    error_string = error_string.replace(&token_unwrapped, "***");
-                }
-
+                let error_string = std::str::from_utf8(_o.stderr.as_slice()).unwrap();
                 panic!("Exit code {}: {}", _o.status, error_string);
             }
         }
-        Err(e) => {
-            let mut error_string = e.to_string();
+        Err(e) => panic!("{}", e),
+    }
+}
 
-            if let Ok(token_unwrapped) = get_keyring().get_password() {
-                error_string = error_string.replace(&token_unwrapped, "***");
-            }
+/// Whether two commit hashes point at the same commit, either of them can be abbreviated
+pub fn same_commit(a: &str, b: &str) -> bool {
+    let a = a.to_lowercase();
+    let b = b.to_lowercase();
+    !a.is_empty() && !b.is_empty() && (a.starts_with(&b) || b.starts_with(&a))
+}
 
-            panic!("{}", error_string);
-        }
+/// The commit that is checked out in a cloned repo
+pub fn resolve_commit(repo: &Path) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("rev-parse")
+        .arg("HEAD")
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
     }
+}
 
-    out.exists()
+/// Applies a patch to the files in a folder, or only checks that it would apply
+///
+/// The folder is never treated as part of a repo it's in, paths in the patch are relative to it
+pub fn apply_patch(dir: &Path, patch: &Path, check_only: bool) -> Result<(), String> {
+    let mut git = patch_command(dir);
+    git.arg("apply").arg("--whitespace=nowarn");
+    if check_only {
+        git.arg("--check");
+    }
+    let output = git
+        .arg(patch)
+        .output()
+        .map_err(|e| format!("Running git failed: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GithubReleaseAsset {
-    pub url: String,
-    pub name: String,
+/// The paths a patch changes, creates or removes, relative to the folder it's applied in
+pub fn patched_paths(dir: &Path, patch: &Path) -> Vec<PathBuf> {
+    let output = patch_command(dir)
+        .arg("apply")
+        .arg("--numstat")
+        .arg("-z")
+        .arg(patch)
+        .output();
+    let stdout = match output {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => return Vec::new(),
+    };
+
+    // each file is added\tremoved\tpath\0, or added\tremoved\t\0old path\0new path\0 for renames
+    let stdout = String::from_utf8_lossy(&stdout);
+    let mut fields = stdout.split('\0').filter(|field| !field.is_empty());
+    let mut paths = Vec::new();
+    while let Some(field) = fields.next() {
+        match field.splitn(3, '\t').nth(2) {
+            Some(path) if !path.is_empty() => paths.push(PathBuf::from(path)),
+            _ => paths.extend(fields.by_ref().take(2).map(PathBuf::from)),
+        }
+    }
+    paths
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GithubReleaseData {
-    pub assets: Vec<GithubReleaseAsset>,
+fn patch_command(dir: &Path) -> std::process::Command {
+    let mut git = std::process::Command::new("git");
+    git.current_dir(dir)
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_WORK_TREE");
+    // stops git from finding the repo of the project the folder is in
+    if let Some(parent) = dir.parent() {
+        git.env("GIT_CEILING_DIRECTORIES", parent);
+    }
+    git
 }
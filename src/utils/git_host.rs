@@ -0,0 +1,588 @@
+use std::{collections::HashMap, lazy::SyncLazy as Lazy, path::Path, sync::Mutex, time::Duration};
+
+use base64::Engine;
+use owo_colors::OwoColorize;
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::{ACCEPT, AUTHORIZATION},
+    Url,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{archive::ArchiveKind, network, progress};
+use crate::data::config::{get_token, normalize_host, Config};
+
+/// The kind of server a url points at, which decides how its urls look and how tokens are passed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum HostKind {
+    GitHub,
+    GitLab,
+    /// Gitea and its fork Forgejo, which share their url layout and api
+    Gitea,
+    /// Any other server that serves git over https, only urls ending in .git are cloned
+    Generic,
+}
+
+impl std::str::FromStr for HostKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "github" => Ok(HostKind::GitHub),
+            "gitlab" => Ok(HostKind::GitLab),
+            "gitea" | "forgejo" => Ok(HostKind::Gitea),
+            "generic" => Ok(HostKind::Generic),
+            _ => Err(format!(
+                "{} is not a kind of git host, use github, gitlab, gitea or generic",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for HostKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HostKind::GitHub => "github",
+            HostKind::GitLab => "gitlab",
+            HostKind::Gitea => "gitea",
+            HostKind::Generic => "generic",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The kind of every host that was looked at this run, starting with the ones in the config
+static HOST_KINDS: Lazy<Mutex<HashMap<String, HostKind>>> = Lazy::new(|| {
+    Mutex::new(
+        Config::read_combine()
+            .git_hosts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|git_host| (normalize_host(&git_host.host), git_host.kind))
+            .collect(),
+    )
+});
+
+/// Hosts that were asked whether they are gitea this run, because an api call was needed for them
+static PROBED_KINDS: Lazy<Mutex<HashMap<String, HostKind>>> = Lazy::new(Default::default);
+
+/// The kind of a host, from the config or its well known name, never by asking it
+///
+/// Urls are parsed for every dependency and lib, so an unknown host must not cost a request
+fn host_kind(host: &str) -> HostKind {
+    if let Some(kind) = HOST_KINDS.lock().unwrap().get(host) {
+        return *kind;
+    }
+
+    let name = host.split(':').next().unwrap_or(host);
+    if name == "github.com" || name == "www.github.com" {
+        HostKind::GitHub
+    } else if name == "gitlab.com" || name.starts_with("gitlab.") {
+        HostKind::GitLab
+    } else if name == "codeberg.org" {
+        HostKind::Gitea
+    } else {
+        HostKind::Generic
+    }
+}
+
+/// Whether the host answers the version api of gitea and forgejo, which doesn't need a token
+fn is_gitea(scheme: &str, host: &str) -> bool {
+    let version_url = format!("{}://{}/api/v1/version", scheme, host);
+    let response = match network::get_agent()
+        .get(&version_url)
+        .timeout(Duration::from_secs(5))
+        .send()
+    {
+        Ok(response) if response.status().is_success() => response,
+        _ => return false,
+    };
+
+    response.json::<serde_json::Value>().map_or(false, |body| {
+        body.get("version").map_or(false, |v| v.is_string())
+    })
+}
+
+/// A url on a git host, split into the repo and whatever comes after it
+#[derive(Debug, Clone)]
+pub struct HostUrl {
+    pub kind: HostKind,
+    url: Url,
+    /// owner/repo, or group/subgroup/repo on gitlab, without .git
+    repo_path: String,
+    /// The path after the repo, like releases/download/tag/file
+    rest: Vec<String>,
+    /// Whether the url ended in .git
+    dot_git: bool,
+}
+
+impl HostUrl {
+    /// Parses an http(s) url, None for anything else
+    pub fn parse(url: &str) -> Option<HostUrl> {
+        let url = Url::parse(url.trim_end_matches('/')).ok()?;
+        if url.scheme() != "https" && url.scheme() != "http" {
+            return None;
+        }
+        url.host_str()?;
+        let segments: Vec<String> = url
+            .path_segments()?
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| segment.to_string())
+            .collect();
+
+        let kind = host_kind(&host_of(&url));
+
+        let (mut repo, rest) = match kind {
+            HostKind::GitHub | HostKind::Gitea => {
+                let split = segments.len().min(2);
+                (segments[..split].to_vec(), segments[split..].to_vec())
+            }
+            HostKind::GitLab => match segments.iter().position(|segment| segment == "-") {
+                Some(dash) => (segments[..dash].to_vec(), segments[dash + 1..].to_vec()),
+                None => (segments, Vec::new()),
+            },
+            HostKind::Generic => (segments, Vec::new()),
+        };
+
+        let mut dot_git = false;
+        if let Some(last) = repo.last_mut() {
+            if let Some(stripped) = last.strip_suffix(".git") {
+                *last = stripped.to_string();
+                dot_git = true;
+            }
+        }
+
+        Some(HostUrl {
+            kind,
+            url,
+            repo_path: repo.join("/"),
+            rest,
+            dot_git,
+        })
+    }
+
+    /// The host name, with the port if there is one
+    pub fn host(&self) -> String {
+        host_of(&self.url)
+    }
+
+    /// Whether this is a repo to clone, and not an archive or release asset on the host
+    pub fn is_repo(&self) -> bool {
+        if ArchiveKind::from_url(self.url.as_str()).is_some() {
+            return false;
+        }
+
+        let depth = self.repo_path.split('/').filter(|s| !s.is_empty()).count();
+        match self.kind {
+            // only host/owner/repo(.git), anything deeper is something like /archive/ or /releases/
+            HostKind::GitHub | HostKind::Gitea => depth == 2 && self.rest.is_empty(),
+            HostKind::GitLab => depth >= 2 && self.rest.is_empty(),
+            HostKind::Generic => self.dot_git,
+        }
+    }
+
+    /// The url git should clone, never with credentials in it
+    pub fn clone_url(&self) -> String {
+        let mut url = self.url.clone();
+        url.set_path(&format!("/{}.git", self.repo_path));
+        url.set_query(None);
+        url.set_fragment(None);
+        url.set_username("").ok();
+        url.set_password(None).ok();
+        url.to_string()
+    }
+
+    /// A folder name for a mirror of this repo, unique per host and repo
+    ///
+    /// The readable part can be the same for different repos (`a_b/c` and `a/b_c`), the hash of the host and repo path after it can't
+    pub fn mirror_name(&self) -> String {
+        let key = format!("{}/{}", self.host(), self.repo_path);
+        let readable: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let digest = format!("{:x}", Sha256::digest(key.as_bytes()));
+        format!("{}-{}.git", readable, &digest[..16])
+    }
+
+    /// This url with the kind its host turns out to have when asked, for hosts that are neither configured nor known by name
+    fn probed(&self) -> HostUrl {
+        let host = self.host();
+        if self.kind != HostKind::Generic || HOST_KINDS.lock().unwrap().contains_key(&host) {
+            return self.clone();
+        }
+
+        let kind = *PROBED_KINDS
+            .lock()
+            .unwrap()
+            .entry(host.clone())
+            .or_insert_with(|| {
+                if is_gitea(self.url.scheme(), &host) {
+                    HostKind::Gitea
+                } else {
+                    HostKind::Generic
+                }
+            });
+        HostUrl {
+            kind,
+            ..self.clone()
+        }
+    }
+
+    /// The git config that makes git send the token to this host (and only this host), as key and value
+    pub fn git_auth_config(&self, token: &str) -> (String, String) {
+        // all of these take the token as basic auth password, gitlab wants this specific user with it
+        let user = match self.kind {
+            HostKind::GitLab => "oauth2",
+            _ => "x-access-token",
+        };
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, token));
+
+        (
+            format!("http.{}://{}/.extraHeader", self.url.scheme(), self.host()),
+            format!("Authorization: Basic {}", credentials),
+        )
+    }
+
+    /// The tag and file name if this is a release asset url
+    pub fn release_asset(&self) -> Option<(String, String)> {
+        match self.kind {
+            // releases/download/tag/file
+            HostKind::GitHub | HostKind::Gitea
+                if self.rest.len() == 4
+                    && self.rest[0] == "releases"
+                    && self.rest[1] == "download" =>
+            {
+                Some((self.rest[2].clone(), self.rest[3].clone()))
+            }
+            // -/releases/tag/downloads/path/to/file
+            HostKind::GitLab
+                if self.rest.len() >= 4
+                    && self.rest[0] == "releases"
+                    && self.rest[2] == "downloads" =>
+            {
+                Some((self.rest[1].clone(), self.rest[3..].join("/")))
+            }
+            _ => None,
+        }
+    }
+
+    /// The token configured for this host, if any
+    pub fn token(&self) -> Option<String> {
+        get_token(&self.host())
+    }
+
+    /// Adds the token to a request the way this host expects it
+    fn authorize(&self, builder: RequestBuilder, token: Option<&str>) -> RequestBuilder {
+        let token = match token {
+            Some(token) => token,
+            None => return builder,
+        };
+
+        match self.kind {
+            HostKind::GitHub | HostKind::Gitea => {
+                builder.header(AUTHORIZATION, format!("token {}", token))
+            }
+            // gitlab also takes bearer tokens, which reqwest drops on redirects to other hosts like object storage, unlike PRIVATE-TOKEN
+            HostKind::GitLab | HostKind::Generic => builder.bearer_auth(token),
+        }
+    }
+
+    /// Downloads the file behind this url, going through the host's api when that's needed for private repos
+    pub fn download(&self, out: &Path) {
+        let token = self.token();
+        match (self.kind, &token, self.release_asset()) {
+            // release downloads on github.com don't take tokens, the api does
+            (HostKind::GitHub, Some(token), Some((tag, file))) => {
+                self.download_github_asset(&tag, &file, token, out)
+            }
+            _ => network::download_file_with(self.url.as_str(), out, |builder| {
+                self.authorize(builder, token.as_deref())
+            }),
+        }
+    }
+
+    /// Downloads a tar.gz of the repo at a branch, tag or commit (the default branch if None), for when git can't be used
+    ///
+    /// The reference is looked up first and the archive of that exact commit is downloaded, so the commit can be pinned like a clone's.
+    /// Returns the url that was downloaded and the commit, None if the host could not tell
+    pub fn download_archive(
+        &self,
+        reference: Option<&str>,
+        out: &Path,
+    ) -> (String, Option<String>) {
+        // the api is needed from here on, so a host that isn't known by name is asked what it is
+        let probed = self.probed();
+        if probed.kind != self.kind {
+            return probed.download_archive(reference, out);
+        }
+        if self.kind == HostKind::Generic {
+            panic!(
+                "{} can only be fetched with git, install git or enable it with {}",
+                self.url.as_str().bright_yellow(),
+                "qpm config git enable".bright_yellow()
+            );
+        }
+
+        let token = self.token();
+        let commit = match self.resolve_commit(reference, token.as_deref()) {
+            Ok(commit) => Some(commit),
+            Err(e) => {
+                progress::println(format!(
+                    "Looking up the commit of {} at {} failed, its commit is not recorded: {}",
+                    self.repo_path.bright_yellow(),
+                    reference.unwrap_or("the default branch").bright_green(),
+                    e
+                ));
+                None
+            }
+        };
+        let reference = commit.as_deref().or(reference);
+
+        let origin = format!("{}://{}", self.url.scheme(), self.host());
+        let archive_url = match self.kind {
+            HostKind::GitHub => match &token {
+                // the archive links on github.com don't take tokens, the api does
+                Some(_) => format!(
+                    "https://api.github.com/repos/{}/tarball/{}",
+                    self.repo_path,
+                    reference.unwrap_or("HEAD")
+                ),
+                None => format!(
+                    "https://github.com/{}/archive/{}.tar.gz",
+                    self.repo_path,
+                    reference.unwrap_or("HEAD")
+                ),
+            },
+            HostKind::GitLab => {
+                let mut url = Url::parse(&format!(
+                    "{}/api/v4/projects/{}/repository/archive.tar.gz",
+                    origin,
+                    self.repo_path.replace('/', "%2F")
+                ))
+                .unwrap();
+                if let Some(reference) = reference {
+                    url.query_pairs_mut().append_pair("sha", reference);
+                }
+                url.to_string()
+            }
+            HostKind::Gitea => {
+                let reference = match reference {
+                    Some(reference) => reference.to_string(),
+                    None => self.gitea_default_branch(token.as_deref()),
+                };
+                format!("{}/{}/archive/{}.tar.gz", origin, self.repo_path, reference)
+            }
+            HostKind::Generic => unreachable!(),
+        };
+
+        network::download_file_with(&archive_url, out, |builder| {
+            self.authorize(builder, token.as_deref())
+        });
+        (archive_url, commit)
+    }
+
+    /// The full commit a branch, tag or commit points at on the host (the default branch if None), asked through its api
+    fn resolve_commit(
+        &self,
+        reference: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<String, String> {
+        let origin = format!("{}://{}", self.url.scheme(), self.host());
+        let commit = match self.kind {
+            HostKind::GitHub => {
+                // the api answers with only the sha when asked for this media type
+                let commit_url = format!(
+                    "https://api.github.com/repos/{}/commits/{}",
+                    self.repo_path,
+                    reference.unwrap_or("HEAD")
+                );
+                self.api_get(&commit_url, token, |builder| {
+                    builder.header(ACCEPT, "application/vnd.github.sha")
+                })?
+                .text()
+                .map_err(|e| e.without_url().to_string())?
+            }
+            HostKind::GitLab => {
+                let project_url = format!(
+                    "{}/api/v4/projects/{}",
+                    origin,
+                    self.repo_path.replace('/', "%2F")
+                );
+                let reference = match reference {
+                    Some(reference) => reference.to_string(),
+                    None => {
+                        self.api_get(&project_url, token, |builder| builder)?
+                            .json::<GitlabProjectData>()
+                            .map_err(|e| e.without_url().to_string())?
+                            .default_branch
+                    }
+                };
+                let commit_url = format!(
+                    "{}/repository/commits/{}",
+                    project_url,
+                    reference.replace('/', "%2F")
+                );
+                self.api_get(&commit_url, token, |builder| builder)?
+                    .json::<GitlabCommitData>()
+                    .map_err(|e| e.without_url().to_string())?
+                    .id
+            }
+            HostKind::Gitea => {
+                // without a sha the newest commit of the default branch is listed
+                let mut commits_url = Url::parse(&format!(
+                    "{}/api/v1/repos/{}/commits?limit=1&stat=false",
+                    origin, self.repo_path
+                ))
+                .unwrap();
+                if let Some(reference) = reference {
+                    commits_url.query_pairs_mut().append_pair("sha", reference);
+                }
+                self.api_get(commits_url.as_str(), token, |builder| builder)?
+                    .json::<Vec<GiteaCommitData>>()
+                    .map_err(|e| e.without_url().to_string())?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| "the repo has no commits".to_string())?
+                    .sha
+            }
+            HostKind::Generic => return Err("the host has no api to ask".to_string()),
+        };
+
+        let commit = commit.trim().to_string();
+        if commit.len() < 40 || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("{} is not a commit", commit));
+        }
+        Ok(commit)
+    }
+
+    /// Sends a get request to the host's api, any answer but a success is an error
+    fn api_get(
+        &self,
+        url: &str,
+        token: Option<&str>,
+        customize: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Response, String> {
+        let response = network::try_send_with_retry(url, || {
+            customize(self.authorize(network::get_agent().get(url), token))
+        })
+        .map_err(|e| e.without_url().to_string())?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "the host answered with status {}",
+                response.status()
+            ));
+        }
+        Ok(response)
+    }
+
+    fn gitea_default_branch(&self, token: Option<&str>) -> String {
+        let repo_url = format!(
+            "{}://{}/api/v1/repos/{}",
+            self.url.scheme(),
+            self.host(),
+            self.repo_path
+        );
+
+        let response = network::send_with_retry(&repo_url, || {
+            self.authorize(network::get_agent().get(&repo_url), token)
+        });
+        if !response.status().is_success() {
+            panic!(
+                "Looking up the default branch of {} failed with status {}",
+                self.repo_path.bright_yellow(),
+                response.status().bright_red()
+            );
+        }
+
+        response.json::<GiteaRepoData>().unwrap().default_branch
+    }
+
+    fn download_github_asset(&self, tag: &str, file: &str, token: &str, out: &Path) {
+        let release_url = format!(
+            "https://api.github.com/repos/{}/releases/tags/{}",
+            self.repo_path, tag
+        );
+
+        let response = network::send_with_retry(&release_url, || {
+            self.authorize(network::get_agent().get(&release_url), Some(token))
+        });
+        if !response.status().is_success() {
+            panic!(
+                "Looking up release {} of {} failed with status {}",
+                tag.bright_green(),
+                self.repo_path.bright_yellow(),
+                response.status().bright_red()
+            );
+        }
+        let data = response.json::<GithubReleaseData>().unwrap();
+
+        let asset = data
+            .assets
+            .iter()
+            .find(|asset| asset.name == file)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Release {} of {} has no asset named {}",
+                    tag.bright_green(),
+                    self.repo_path.bright_yellow(),
+                    file.bright_yellow()
+                )
+            });
+
+        // the asset api url only gives the file instead of its metadata when asked for it
+        network::download_file_with(&asset.url, out, |builder| {
+            self.authorize(builder, Some(token))
+                .header(ACCEPT, "application/octet-stream")
+        });
+    }
+}
+
+fn host_of(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GithubReleaseAsset {
+    pub url: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GithubReleaseData {
+    pub assets: Vec<GithubReleaseAsset>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GiteaRepoData {
+    pub default_branch: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GiteaCommitData {
+    pub sha: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GitlabProjectData {
+    pub default_branch: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GitlabCommitData {
+    pub id: String,
+}
@@ -0,0 +1,26 @@
+use std::path::Path;
+
+use owo_colors::OwoColorize;
+use sha2::{Digest, Sha256};
+
+/// The lowercase hex sha256 digest of the file at the given path
+pub fn sha256_file(path: &Path) -> String {
+    let mut file = std::fs::File::open(path).unwrap_or_else(|e| {
+        panic!(
+            "Opening {} to hash failed: {}",
+            path.display().bright_yellow(),
+            e
+        )
+    });
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).unwrap_or_else(|e| {
+        panic!(
+            "Reading {} to hash failed: {}",
+            path.display().bright_yellow(),
+            e
+        )
+    });
+
+    format!("{:x}", hasher.finalize())
+}
@@ -0,0 +1,77 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use fs2::FileExt;
+use owo_colors::OwoColorize;
+
+/// Folder in the cache that holds the locks of all entries
+///
+/// Kept outside of the entries, so removing an entry never removes a lock another process is holding or waiting on
+pub const LOCKS_DIR: &str = ".locks";
+
+/// The lock of the cache entry at <cache>/<id>/<version>, which is <cache>/.locks/<id>/<version>.lock
+///
+/// Repo mirrors at <cache>/.mirrors/<name> are locked the same way
+pub fn entry_lock_path(entry_path: &Path) -> PathBuf {
+    let id_path = entry_path.parent().unwrap();
+    id_path
+        .parent()
+        .unwrap()
+        .join(LOCKS_DIR)
+        .join(id_path.file_name().unwrap())
+        .join(format!(
+            "{}.lock",
+            entry_path.file_name().unwrap().to_string_lossy()
+        ))
+}
+
+fn open_lock(path: &Path) -> File {
+    std::fs::create_dir_all(path.parent().unwrap()).expect("Failed to create lock folder");
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "Opening lock file {} failed: {}",
+                path.display().bright_yellow(),
+                e
+            )
+        })
+}
+
+/// Blocks until no other process holds the lock, the lock is released when the file is dropped
+pub fn lock_exclusive(path: &Path) -> File {
+    let file = open_lock(path);
+    if file.try_lock_exclusive().is_err() {
+        println!(
+            "Waiting for another qpm process to release {}",
+            path.display().bright_yellow()
+        );
+        file.lock_exclusive().expect("Locking failed");
+    }
+    file
+}
+
+/// Blocks until no other process holds the lock exclusively, other shared holders are fine
+pub fn lock_shared(path: &Path) -> File {
+    let file = open_lock(path);
+    if file.try_lock_shared().is_err() {
+        println!(
+            "Waiting for another qpm process to release {}",
+            path.display().bright_yellow()
+        );
+        file.lock_shared().expect("Locking failed");
+    }
+    file
+}
+
+/// Takes the lock only if nobody else holds it
+pub fn try_lock_exclusive(path: &Path) -> Option<File> {
+    let file = open_lock(path);
+    file.try_lock_exclusive().ok().map(|_| file)
+}
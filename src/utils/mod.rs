@@ -1,2 +1,10 @@
+pub mod archive;
 pub mod git;
+pub mod git_host;
+pub mod hash;
+pub mod lock;
+pub mod network;
+pub mod progress;
+pub mod redact;
+pub mod sync;
 pub mod toggle;
\ No newline at end of file
@@ -0,0 +1,298 @@
+use std::{lazy::SyncLazy as Lazy, path::Path, time::Duration};
+
+use owo_colors::OwoColorize;
+use reqwest::{
+    blocking::{Client, ClientBuilder, RequestBuilder, Response},
+    header::{ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    StatusCode,
+};
+
+use super::progress;
+use crate::data::config::Config;
+
+/// Client used for small requests, like the registry api
+static AGENT: Lazy<Client> = Lazy::new(|| {
+    let config = Config::read_combine();
+    build_client(&config, config.timeout.unwrap_or(5000))
+});
+
+/// Client used for large downloads, which gets a longer timeout
+static DOWNLOAD_AGENT: Lazy<Client> = Lazy::new(|| {
+    let config = Config::read_combine();
+    build_client(&config, config.download_timeout.unwrap_or(60000))
+});
+
+/// The blocking client's timeout is a deadline for the answer and for reading a whole json or text body,
+/// but it starts over for every read of a streamed body, so downloads only time out when they stall
+fn build_client(config: &Config, timeout: u64) -> Client {
+    let mut builder = ClientBuilder::new()
+        .timeout(Duration::from_millis(timeout))
+        .connect_timeout(Duration::from_millis(
+            config.connect_timeout.unwrap_or(5000),
+        ))
+        .user_agent(format!(
+            "questpackagemanager-rust/{}",
+            env!("CARGO_PKG_VERSION")
+        ));
+
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(
+            reqwest::Proxy::all(proxy)
+                .unwrap_or_else(|e| panic!("Invalid proxy url {}: {}", proxy.bright_yellow(), e)),
+        );
+    }
+
+    if let Some(certificates) = &config.ca_certificates {
+        for path in certificates.iter() {
+            builder = builder.add_root_certificate(read_certificate(path));
+        }
+    }
+
+    builder.build().expect("failed to build reqwest client")
+}
+
+/// Reads a root certificate, a .der file is binary and anything else is taken as pem
+pub fn read_certificate(path: &Path) -> reqwest::Certificate {
+    let bytes = std::fs::read(path).unwrap_or_else(|e| {
+        panic!(
+            "Reading certificate {} failed: {}",
+            path.display().bright_yellow(),
+            e
+        )
+    });
+
+    let certificate = if path.extension().map_or(false, |ext| ext == "der") {
+        reqwest::Certificate::from_der(&bytes)
+    } else {
+        reqwest::Certificate::from_pem(&bytes)
+    };
+
+    certificate.unwrap_or_else(|e| {
+        panic!(
+            "Parsing certificate {} failed: {}",
+            path.display().bright_yellow(),
+            e
+        )
+    })
+}
+
+/// The client to use for api requests
+pub fn get_agent() -> &'static Client {
+    &AGENT
+}
+
+/// The client to use for downloading artifacts
+pub fn get_download_agent() -> &'static Client {
+    &DOWNLOAD_AGENT
+}
+
+/// How many attempts a request or clone gets before giving up
+pub fn get_attempts() -> u32 {
+    static ATTEMPTS: Lazy<u32> = Lazy::new(|| Config::read_combine().retries.unwrap_or(3).max(1));
+    *ATTEMPTS
+}
+
+/// Waits before the next attempt, doubling the wait every attempt
+pub fn backoff(attempt: u32) {
+    let wait = Duration::from_millis(500 * 2u64.pow(attempt.min(6)));
+    std::thread::sleep(wait);
+}
+
+/// Whether a response status is worth trying again
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Whether a failed request is worth trying again, things like resets and timeouts
+fn is_transient_error(error: &reqwest::Error) -> bool {
+    !error.is_builder() && !error.is_redirect() && !error.is_status()
+}
+
+/// Sends the request made by `make_request`, retrying transient failures with exponential backoff
+///
+/// Non transient error statuses like 404 are returned as is, so the caller can decide what to do with them
+pub fn send_with_retry<F>(url: &str, make_request: F) -> Response
+where
+    F: Fn() -> RequestBuilder,
+{
+    try_send_with_retry(url, make_request).unwrap_or_else(|e| {
+        panic!(
+            "Request to {} failed after {} attempts: {}",
+            url.bright_yellow(),
+            get_attempts(),
+            e.without_url()
+        )
+    })
+}
+
+/// Same as send_with_retry, but returns the error when the server could not be reached at all
+pub fn try_send_with_retry<F>(url: &str, make_request: F) -> reqwest::Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let attempts = get_attempts();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match make_request().send() {
+            Ok(response) if attempt < attempts && is_transient_status(response.status()) => {
+                progress::println(format!(
+                    "Request to {} returned {}, retrying ({}/{})",
+                    url.bright_yellow(),
+                    response.status().bright_red(),
+                    attempt,
+                    attempts
+                ));
+            }
+            Ok(response) => return Ok(response),
+            Err(e) if attempt < attempts && is_transient_error(&e) => {
+                progress::println(format!(
+                    "Request to {} failed: {}, retrying ({}/{})",
+                    url.bright_yellow(),
+                    e.without_url().bright_red(),
+                    attempt,
+                    attempts
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+
+        backoff(attempt);
+    }
+}
+
+/// Downloads the url into a file at the given path
+///
+/// The download is written into a .part file next to the output first,
+/// if the connection drops the download is resumed from where it left off with a range request.
+/// The ETag or Last-Modified of the file is kept next to it, so a file that changed on the server since is downloaded again instead
+pub fn download_file(url: &str, out: &Path) {
+    download_file_with(url, out, |builder| builder);
+}
+
+/// Same as download_file, but allows adding things like headers to every request that is sent
+pub fn download_file_with<F>(url: &str, out: &Path, decorate: F)
+where
+    F: Fn(RequestBuilder) -> RequestBuilder,
+{
+    let mut part_name = out.file_name().unwrap().to_os_string();
+    part_name.push(".part");
+    let part_path = out.with_file_name(&part_name);
+    part_name.push(".validator");
+    let validator_path = out.with_file_name(part_name);
+    // the url could hold a token, so the file name is what we show
+    let label = out.file_name().unwrap().to_string_lossy().to_string();
+
+    let attempts = get_attempts();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        // without knowing which version of the file the .part is from, continuing it could mix two versions
+        let stored = std::fs::read_to_string(&validator_path).ok();
+        if stored.is_none() {
+            std::fs::remove_file(&part_path).ok();
+        }
+        let existing = std::fs::metadata(&part_path).map_or(0, |m| m.len());
+
+        let mut response = send_with_retry(url, || {
+            let builder = decorate(get_download_agent().get(url));
+            match &stored {
+                // the server sends the whole file instead if it changed since
+                Some(stored) if existing > 0 => builder
+                    .header(RANGE, format!("bytes={}-", existing))
+                    .header(IF_RANGE, stored.as_str()),
+                _ => builder,
+            }
+        });
+
+        let current = validator(&response);
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        let mut file = match response.status() {
+            // a server that ignores If-Range could still continue with a newer file
+            StatusCode::PARTIAL_CONTENT if current.is_some() && current != stored => {
+                std::fs::remove_file(&part_path).ok();
+                std::fs::remove_file(&validator_path).ok();
+                continue;
+            }
+            // server gave us the rest of the file, append it
+            StatusCode::PARTIAL_CONTENT => std::fs::OpenOptions::new()
+                .append(true)
+                .open(&part_path)
+                .expect("Failed to open partial download"),
+            // server does not do ranges, or this is a fresh download
+            status if status.is_success() => {
+                match &current {
+                    Some(current) => std::fs::write(&validator_path, current)
+                        .expect("Failed to write download validator"),
+                    None => {
+                        std::fs::remove_file(&validator_path).ok();
+                    }
+                }
+                std::fs::File::create(&part_path).expect("Failed to create partial download")
+            }
+            // the partial file is not something the server can continue, start over
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                std::fs::remove_file(&part_path).ok();
+                std::fs::remove_file(&validator_path).ok();
+                continue;
+            }
+            status => panic!(
+                "Request to {} failed with status {}",
+                url.bright_yellow(),
+                status.bright_red()
+            ),
+        };
+
+        let already = if resumed { existing } else { 0 };
+        let bar = progress::download(&label, response.content_length().map(|len| len + already));
+        bar.set_position(already);
+
+        match std::io::copy(&mut bar.wrap_read(&mut response), &mut file) {
+            Ok(_) => {
+                progress::finish(&bar, format!("Downloaded {}", label.bright_yellow()));
+                break;
+            }
+            Err(e) if attempt < attempts => {
+                bar.finish_and_clear();
+                progress::println(format!(
+                    "Download of {} was interrupted: {}, resuming ({}/{})",
+                    label.bright_yellow(),
+                    e.bright_red(),
+                    attempt,
+                    attempts
+                ));
+                backoff(attempt);
+            }
+            Err(e) => panic!(
+                "Downloading {} failed after {} attempts: {}",
+                label.bright_yellow(),
+                attempt,
+                e
+            ),
+        }
+    }
+
+    std::fs::rename(&part_path, out).unwrap_or_else(|e| {
+        panic!(
+            "Moving finished download to {} failed: {}",
+            out.display().bright_yellow(),
+            e
+        )
+    });
+    std::fs::remove_file(&validator_path).ok();
+}
+
+/// What tells versions of the file apart, a strong ETag or else the Last-Modified date, weak ETags can't be used with If-Range
+fn validator(response: &Response) -> Option<String> {
+    let headers = response.headers();
+    headers
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| {
+            headers
+                .get(LAST_MODIFIED)
+                .and_then(|date| date.to_str().ok())
+        })
+        .map(|validator| validator.to_string())
+}
@@ -0,0 +1,84 @@
+use std::{lazy::SyncLazy as Lazy, time::Duration};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use super::redact;
+
+/// Every bar is drawn through this, so parallel downloads don't draw over each other
+static MULTI: Lazy<MultiProgress> =
+    Lazy::new(|| MultiProgress::with_draw_target(ProgressDrawTarget::stdout()));
+
+/// Whether bars are drawn, when stdout is not a terminal we print plain lines instead
+pub fn is_interactive() -> bool {
+    !MULTI.is_hidden()
+}
+
+/// Prints a line without breaking any bars that are being drawn, with any tokens in it redacted
+pub fn println(message: impl AsRef<str>) {
+    let message = redact::redact(message.as_ref());
+    if is_interactive() {
+        MULTI.println(message).ok();
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// A bar for a download with bytes and rate, len is None if the server didn't tell us the size
+pub fn download(label: &str, len: Option<u64>) -> ProgressBar {
+    if !is_interactive() {
+        println("Downloading ".to_string() + label);
+        return ProgressBar::hidden();
+    }
+
+    let bar = match len {
+        Some(len) => ProgressBar::new(len).with_style(
+            ProgressStyle::with_template(
+                "{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} eta {eta}",
+            )
+            .unwrap()
+            .progress_chars("=> "),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {msg} {bytes} {bytes_per_sec}").unwrap(),
+        ),
+    };
+
+    MULTI.add(bar.with_message(redact::redact(label)))
+}
+
+/// A spinner for work we can't measure, like git clones
+pub fn spinner(label: &str) -> ProgressBar {
+    if !is_interactive() {
+        println(label);
+        return ProgressBar::hidden();
+    }
+
+    let bar = MULTI.add(
+        ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template("{spinner} {msg} {elapsed}").unwrap())
+            .with_message(redact::redact(label)),
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+/// The overall "n/m packages" bar
+pub fn packages(len: u64) -> ProgressBar {
+    if !is_interactive() {
+        return ProgressBar::hidden();
+    }
+
+    MULTI.add(
+        ProgressBar::new(len).with_style(
+            ProgressStyle::with_template("[{bar:30}] {pos}/{len} packages")
+                .unwrap()
+                .progress_chars("=> "),
+        ),
+    )
+}
+
+/// Removes a finished bar, and logs the message in its place
+pub fn finish(bar: &ProgressBar, message: impl AsRef<str>) {
+    bar.finish_and_clear();
+    println(message);
+}
@@ -0,0 +1,230 @@
+use std::{
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use owo_colors::OwoColorize;
+use remove_dir_all::remove_dir_all;
+
+/// Makes `to` a copy of `from`, only writing files whose content differs so unchanged files keep their modification time
+///
+/// Anything in `to` that isn't in `from` is removed, symlinks in `from` are recreated as the same symlink
+///
+/// Returns whether anything changed
+pub fn copy_path(from: &Path, to: &Path) -> bool {
+    let from_metadata = match std::fs::symlink_metadata(from) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+
+    if from_metadata.file_type().is_symlink() {
+        let target = std::fs::read_link(from).unwrap_or_else(|e| {
+            panic!(
+                "Reading the link {} failed: {}",
+                from.display().bright_yellow(),
+                e
+            )
+        });
+        return link_path(&target, to).unwrap_or_else(|e| {
+            panic!(
+                "Creating the link {} failed: {}",
+                to.display().bright_yellow(),
+                e
+            )
+        });
+    }
+
+    let to_metadata = std::fs::symlink_metadata(to).ok();
+    if from_metadata.is_dir() {
+        let mut changed = false;
+        if to_metadata.map_or(true, |metadata| !metadata.is_dir()) {
+            remove_path(to);
+            std::fs::create_dir_all(to).expect("Failed to create destination folder");
+            changed = true;
+        }
+
+        // whatever is left over from an older version of the package
+        for entry in std::fs::read_dir(to).expect("Failed to read destination folder") {
+            let entry = entry.expect("Failed to read destination folder");
+            if std::fs::symlink_metadata(from.join(entry.file_name())).is_err() {
+                remove_path(&entry.path());
+                changed = true;
+            }
+        }
+
+        for entry in std::fs::read_dir(from).expect("Failed to read source folder") {
+            let entry = entry.expect("Failed to read source folder");
+            changed |= copy_path(&entry.path(), &to.join(entry.file_name()));
+        }
+        return changed;
+    }
+
+    if let Some(to_metadata) = to_metadata {
+        if to_metadata.is_file() && same_content(from, to) {
+            return false;
+        }
+        remove_path(to);
+    }
+
+    std::fs::create_dir_all(to.parent().unwrap()).expect("Failed to create parent folder");
+    std::fs::copy(from, to).unwrap_or_else(|e| {
+        panic!(
+            "Copying {} to {} failed: {}",
+            from.display().bright_yellow(),
+            to.display().bright_yellow(),
+            e
+        )
+    });
+    true
+}
+
+/// Makes `to` a symlink to `from`, leaving it alone if it already is one
+///
+/// Returns whether anything changed
+pub fn link_path(from: &Path, to: &Path) -> std::io::Result<bool> {
+    if std::fs::read_link(to).map_or(false, |target| target == from) {
+        return Ok(false);
+    }
+
+    remove_path(to);
+    std::fs::create_dir_all(to.parent().unwrap())?;
+    symlink::symlink_auto(from, to)?;
+    Ok(true)
+}
+
+/// Removes everything in `dir` that isn't one of the `keep` paths or a folder on the way to one
+///
+/// Returns the paths that were removed
+pub fn prune(dir: &Path, keep: &[PathBuf]) -> Vec<PathBuf> {
+    let mut removed = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return removed,
+    };
+
+    for entry in entries {
+        let path = entry.expect("Failed to read folder").path();
+        if keep.contains(&path) {
+            continue;
+        }
+
+        let is_dir = std::fs::symlink_metadata(&path).map_or(false, |metadata| metadata.is_dir());
+        if is_dir && keep.iter().any(|keep_path| keep_path.starts_with(&path)) {
+            removed.extend(prune(&path, keep));
+        } else {
+            remove_path(&path);
+            removed.push(path);
+        }
+    }
+    removed
+}
+
+/// Writes a file only if its content would change, so build tools don't see it as modified
+pub fn write_if_changed(path: &Path, content: &str) {
+    if std::fs::read_to_string(path).map_or(false, |existing| existing == content) {
+        return;
+    }
+
+    std::fs::write(path, content)
+        .unwrap_or_else(|e| panic!("Writing {} failed: {}", path.display().bright_yellow(), e));
+}
+
+/// Whether a relative path from a package stays inside the folder it is joined to, so no `..`, root or drive in it
+pub fn is_contained(path: &Path) -> bool {
+    path.components()
+        .any(|component| matches!(component, Component::Normal(_)))
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Removes a file, folder or symlink, without following symlinks
+pub fn remove_path(path: &Path) {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+
+    let result = if metadata.file_type().is_symlink() {
+        // windows has separate links for folders and files, and the target may be gone
+        symlink::remove_symlink_file(path).or_else(|_| symlink::remove_symlink_dir(path))
+    } else if metadata.is_dir() {
+        remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+
+    result.unwrap_or_else(|e| panic!("Removing {} failed: {}", path.display().bright_yellow(), e));
+}
+
+/// Makes sure a path can be changed without changing what a symlink points at
+///
+/// Symlinked folders on the way are replaced by real folders with links to everything that was in them, and a symlinked file by a copy
+pub fn split_symlinks(root: &Path, relative: &Path) {
+    let mut current = root.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        let is_symlink = match std::fs::symlink_metadata(&current) {
+            Ok(metadata) => metadata.file_type().is_symlink(),
+            // a file the patch creates, everything above it was split already
+            Err(_) => return,
+        };
+        if !is_symlink {
+            continue;
+        }
+
+        let target = current.canonicalize().unwrap_or_else(|e| {
+            panic!(
+                "Following the link {} failed: {}",
+                current.display().bright_yellow(),
+                e
+            )
+        });
+        if target.is_dir() {
+            symlink::remove_symlink_dir(&current).expect("Failed to remove folder link");
+            std::fs::create_dir(&current).expect("Failed to create folder");
+            for entry in std::fs::read_dir(&target).expect("Failed to read linked folder") {
+                let entry = entry.expect("Failed to read linked folder");
+                symlink::symlink_auto(entry.path(), current.join(entry.file_name()))
+                    .expect("Failed to create link");
+            }
+        } else {
+            symlink::remove_symlink_file(&current).expect("Failed to remove file link");
+            std::fs::copy(&target, &current).unwrap_or_else(|e| {
+                panic!(
+                    "Copying {} out of the cache failed: {}",
+                    target.display().bright_yellow(),
+                    e
+                )
+            });
+        }
+    }
+}
+
+fn same_content(a: &Path, b: &Path) -> bool {
+    let same_len = match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.len() == b.len(),
+        _ => false,
+    };
+    if !same_len {
+        return false;
+    }
+
+    let (mut a, mut b) = match (std::fs::File::open(a), std::fs::File::open(b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return false,
+    };
+
+    let mut a_buf = [0u8; 64 * 1024];
+    let mut b_buf = [0u8; 64 * 1024];
+    loop {
+        let read = match a.read(&mut a_buf) {
+            Ok(0) => return true,
+            Ok(read) => read,
+            Err(_) => return false,
+        };
+        if b.read_exact(&mut b_buf[..read]).is_err() || a_buf[..read] != b_buf[..read] {
+            return false;
+        }
+    }
+}
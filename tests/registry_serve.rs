@@ -0,0 +1,360 @@
+//! Drives `qpm registry serve` over http, the way restore and publish talk to a registry
+
+use std::{
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    time::{Duration, Instant},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use reqwest::{
+    blocking::Client,
+    header::{AUTHORIZATION, ETAG, IF_RANGE, RANGE},
+    StatusCode,
+};
+
+const PACKAGE: &str = r#"{
+    "config": {
+        "sharedDir": "shared",
+        "dependenciesDir": "extern",
+        "info": {
+            "name": "Foo",
+            "id": "foo",
+            "version": "1.0.0",
+            "url": null,
+            "additionalData": {}
+        },
+        "dependencies": [],
+        "additionalData": {}
+    },
+    "restoredDependencies": []
+}"#;
+
+/// A running `qpm registry serve`, stopped and removed again when dropped
+struct Registry {
+    child: Child,
+    root: PathBuf,
+    url: String,
+}
+
+impl Registry {
+    fn start(name: &str, extra_args: &[&str]) -> Registry {
+        let root =
+            std::env::temp_dir().join(format!("qpm_registry_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&root).ok();
+        std::fs::create_dir_all(root.join("packages")).unwrap();
+        std::fs::create_dir_all(root.join("artifacts")).unwrap();
+
+        // the os hands out a free port, which is given back right before the registry takes it
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_qpm-rust"))
+            .arg("registry")
+            .arg("serve")
+            .arg(root.join("packages"))
+            .arg("--port")
+            .arg(port.to_string())
+            .arg("--artifacts")
+            .arg(root.join("artifacts"))
+            .args(extra_args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Starting qpm registry serve failed");
+
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "registry did not start listening"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        Registry {
+            child,
+            root,
+            url: format!("http://127.0.0.1:{}", port),
+        }
+    }
+
+    fn get(&self, path: &str) -> reqwest::blocking::Response {
+        Client::new()
+            .get(format!("{}{}", self.url, path))
+            .send()
+            .unwrap()
+    }
+
+    /// Runs qpm in a folder of the registry's root, with a global config that only knows this registry
+    fn qpm(&self, dir: &str, args: &[&str], token: Option<&str>) -> Output {
+        // where the dirs crate looks for the config with the environment set below
+        let config_dir = if cfg!(target_os = "macos") {
+            self.root.join("Library").join("Application Support")
+        } else {
+            self.root.join("config")
+        }
+        .join("QPM-Rust");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(
+            config_dir.join("qpm.settings.json"),
+            serde_json::json!({
+                "cache": self.root.join("cache"),
+                "symlink": false,
+                "registries": [{ "url": self.url }],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let host = self.url.trim_start_matches("http://");
+        let token_var: String = host
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_qpm-rust"));
+        command
+            .args(args)
+            .current_dir(self.root.join(dir))
+            // keeps the config and cache of whoever runs the tests out of it
+            .env("HOME", &self.root)
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("APPDATA", self.root.join("config"))
+            .env(format!("QPM_TOKEN_{}", token_var), token.unwrap_or(""));
+        command.output().expect("Running qpm failed")
+    }
+
+    fn publish(&self, path: &str, auth: Option<&str>) -> reqwest::blocking::Response {
+        let mut request = Client::new()
+            .post(format!("{}{}", self.url, path))
+            .body(PACKAGE);
+        if let Some(auth) = auth {
+            request = request.header(AUTHORIZATION, auth);
+        }
+        request.send().unwrap()
+    }
+}
+
+impl Drop for Registry {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+        std::fs::remove_dir_all(&self.root).ok();
+    }
+}
+
+#[test]
+fn published_packages_are_listed_and_served() {
+    let registry = Registry::start("publish", &[]);
+    assert_eq!(registry.get("/").text().unwrap(), "[]");
+    assert_eq!(registry.get("/foo").status(), StatusCode::NOT_FOUND);
+
+    assert_eq!(
+        registry.publish("/foo/1.0.0", None).status(),
+        StatusCode::OK
+    );
+
+    assert_eq!(registry.get("/").text().unwrap(), r#"["foo"]"#);
+    assert_eq!(
+        registry.get("/foo?limit=0").text().unwrap(),
+        r#"[{"id":"foo","version":"1.0.0"}]"#
+    );
+
+    let served: serde_json::Value = registry.get("/foo/1.0.0").json().unwrap();
+    assert_eq!(served["config"]["info"]["id"], "foo");
+    assert_eq!(registry.get("/foo/2.0.0").status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn publishing_checks_the_package_and_auth() {
+    let registry = Registry::start("auth", &["--auth", "secret"]);
+    assert_eq!(
+        registry.publish("/foo/1.0.0", None).status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        registry.publish("/bar/1.0.0", Some("secret")).status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        registry.publish("/foo/1.0.0", Some("secret")).status(),
+        StatusCode::OK
+    );
+}
+
+#[test]
+fn storage_errors_are_server_errors() {
+    let registry = Registry::start("storage", &[]);
+    // a file where the folder of the package should go
+    std::fs::write(registry.root.join("packages").join("foo"), "").unwrap();
+
+    assert_eq!(
+        registry.publish("/foo/1.0.0", None).status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[test]
+fn artifacts_can_be_resumed() {
+    let registry = Registry::start("artifacts", &[]);
+    std::fs::write(
+        registry.root.join("artifacts").join("libfoo.so"),
+        "0123456789",
+    )
+    .unwrap();
+
+    assert_eq!(
+        registry.get("/artifacts/libfoo.so").text().unwrap(),
+        "0123456789"
+    );
+
+    let resumed = Client::new()
+        .get(format!("{}/artifacts/libfoo.so", registry.url))
+        .header(RANGE, "bytes=4-")
+        .send()
+        .unwrap();
+    assert_eq!(resumed.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resumed.text().unwrap(), "456789");
+
+    // resuming is only allowed for the same file, which qpm checks with the etag
+    let etag = registry
+        .get("/artifacts/libfoo.so")
+        .headers()
+        .get(ETAG)
+        .expect("artifacts need an etag to be resumed")
+        .to_str()
+        .unwrap()
+        .to_string();
+    let same_file = Client::new()
+        .get(format!("{}/artifacts/libfoo.so", registry.url))
+        .header(RANGE, "bytes=4-")
+        .header(IF_RANGE, &etag)
+        .send()
+        .unwrap();
+    assert_eq!(same_file.status(), StatusCode::PARTIAL_CONTENT);
+    let other_file = Client::new()
+        .get(format!("{}/artifacts/libfoo.so", registry.url))
+        .header(RANGE, "bytes=4-")
+        .header(IF_RANGE, "\"something else\"")
+        .send()
+        .unwrap();
+    assert_eq!(other_file.status(), StatusCode::OK);
+    assert_eq!(other_file.text().unwrap(), "0123456789");
+
+    let past_end = Client::new()
+        .get(format!("{}/artifacts/libfoo.so", registry.url))
+        .header(RANGE, "bytes=10-")
+        .send()
+        .unwrap();
+    assert_eq!(past_end.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    assert_eq!(
+        registry.get("/artifacts/missing.so").status(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+/// Writes a package folder with a qpm.json and qpm.shared.json for it
+fn write_package(dir: &Path, config: serde_json::Value) {
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("qpm.json"), config.to_string()).unwrap();
+    std::fs::write(
+        dir.join("qpm.shared.json"),
+        serde_json::json!({ "config": config, "restoredDependencies": [] }).to_string(),
+    )
+    .unwrap();
+}
+
+fn package_config(
+    id: &str,
+    url: Option<&str>,
+    dependencies: serde_json::Value,
+) -> serde_json::Value {
+    serde_json::json!({
+        "sharedDir": "shared",
+        "dependenciesDir": "extern",
+        "info": {
+            "name": id,
+            "id": id,
+            "version": "1.0.0",
+            "url": url,
+            "additionalData": { "headersOnly": true }
+        },
+        "dependencies": dependencies,
+        "additionalData": {}
+    })
+}
+
+#[test]
+fn published_packages_restore_without_internet() {
+    let registry = Registry::start("restore", &["--auth", "secret"]);
+    let config = package_config(
+        "lib",
+        Some(&format!("{}/artifacts/lib-1.0.0.tar.gz", registry.url)),
+        serde_json::json!([]),
+    );
+
+    // the sources of lib, as a tar.gz with a top level folder like the archives of git hosts
+    let mut archive = tar::Builder::new(GzEncoder::new(
+        std::fs::File::create(registry.root.join("artifacts").join("lib-1.0.0.tar.gz")).unwrap(),
+        Compression::default(),
+    ));
+    for (path, content) in [
+        ("lib/qpm.json", config.to_string()),
+        ("lib/shared/lib.hpp", "#pragma once\n".to_string()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, path, content.as_bytes())
+            .unwrap();
+    }
+    archive.into_inner().unwrap().finish().unwrap();
+
+    write_package(&registry.root.join("lib"), config);
+    // a publish the registry refuses is an error, not something that looks like it worked
+    let refused = registry.qpm("lib", &["publish"], None);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("401"));
+    assert_eq!(registry.get("/lib?limit=0").status(), 404);
+
+    let published = registry.qpm("lib", &["publish"], Some("secret"));
+    assert!(
+        published.status.success(),
+        "publish failed: {}",
+        String::from_utf8_lossy(&published.stderr)
+    );
+    assert_eq!(
+        registry.get("/lib?limit=0").text().unwrap(),
+        r#"[{"id":"lib","version":"1.0.0"}]"#
+    );
+
+    write_package(
+        &registry.root.join("app"),
+        package_config(
+            "app",
+            None,
+            serde_json::json!([{ "id": "lib", "versionRange": "^1.0.0", "additionalData": {} }]),
+        ),
+    );
+    let restored = registry.qpm("app", &["restore"], None);
+    assert!(
+        restored.status.success(),
+        "restore failed: {}",
+        String::from_utf8_lossy(&restored.stderr)
+    );
+
+    let app = registry.root.join("app");
+    assert!(app.join("extern/includes/lib/shared/lib.hpp").exists());
+    let lock: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(app.join("qpm.shared.json")).unwrap())
+            .unwrap();
+    let restored_lib = &lock["restoredDependencies"][0];
+    assert_eq!(restored_lib["dependency"]["id"], "lib");
+    assert_eq!(restored_lib["version"], "1.0.0");
+    assert!(restored_lib["checksums"]["source"].is_string());
+}